@@ -0,0 +1,94 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use regex::Regex;
+use reqwest::Url;
+use std::fmt::Write;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use webgrep::cache::MemoryCache;
+use webgrep::{run, Config};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+const PAGES: usize = 1000;
+
+/// Pages `/0` to `/999`,
+/// each linking to a few others,
+/// so every page is reachable from `/0`.
+struct Site;
+
+impl Respond for Site {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        match request.url.path()[1..].parse::<usize>() {
+            Ok(i) if i < PAGES => {
+                let mut body = format!("<h1>Page {}</h1>", i);
+                for j in [i + 1, i * 7 + 3, i * 31 + 17] {
+                    write!(
+                        body,
+                        r#"<p>See <a href="/{0}">page {0}</a>.</p>"#,
+                        j % PAGES
+                    )
+                    .unwrap();
+                }
+                ResponseTemplate::new(200).set_body_raw(body, "text/html")
+            }
+            _ => ResponseTemplate::new(404),
+        }
+    }
+}
+
+fn crawl_config(workers: usize) -> Config {
+    Config {
+        request_delay: Duration::ZERO,
+        page_threads: NonZeroUsize::new(workers).unwrap(),
+        max_depth: None,
+        skip_visited: true,
+        exact_visited: true,
+        ..Config::new(Regex::new("Page 999").unwrap())
+    }
+}
+
+async fn crawl(client: &'static reqwest::Client, config: &'static Config, url: &Url) -> u64 {
+    run(
+        std::io::sink(),
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        Arc::new(MemoryCache::new()),
+        client,
+        config,
+        vec![url.clone()],
+    )
+    .await
+    .unwrap()
+    .pages
+}
+
+fn bench_crawl(c: &mut Criterion) {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    let server = rt.block_on(async {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(Site)
+            .mount(&server)
+            .await;
+        server
+    });
+    let url = Url::parse(&format!("{}/0", server.uri())).unwrap();
+    let client: &'static _ = Box::leak(Box::new(reqwest::Client::new()));
+
+    let mut group = c.benchmark_group("crawl");
+    // Each iteration crawls every page.
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(10));
+    group.throughput(Throughput::Elements(PAGES as u64));
+    for workers in [1, 2, 4, 8] {
+        let config: &'static _ = Box::leak(Box::new(crawl_config(workers)));
+        assert_eq!(rt.block_on(crawl(client, config, &url)), PAGES as u64);
+        group.bench_with_input(BenchmarkId::new("workers", workers), &workers, |b, _| {
+            b.iter(|| rt.block_on(crawl(client, config, &url)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_crawl);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::RcDom;
+use regex::Regex;
+use reqwest::Url;
+use std::fmt::Write;
+use webgrep::Config;
+
+// A large article,
+// shaped like a Wikipedia page,
+// with navigation, infobox, paragraphs, references, and scripts.
+fn article() -> String {
+    let mut s = String::from(
+        r#"<!DOCTYPE html><html lang="en"><head><meta charset="utf-8"><title>Article</title>
+<link rel="stylesheet" href="/w/load.css"><script>var config = {"wgPageName": "Article"};</script></head>
+<body><div id="mw-navigation"><ul>"#,
+    );
+    for i in 0..200 {
+        write!(
+            s,
+            r#"<li><a href="/wiki/Portal:{i}" title="Portal {i}">Portal {i}</a></li>"#
+        )
+        .unwrap();
+    }
+    s.push_str(r#"</ul></div><div id="content"><h1>Article</h1><table class="infobox"><tbody>"#);
+    for i in 0..40 {
+        write!(
+            s,
+            r#"<tr><th scope="row">Field {i}</th><td><a href="/wiki/Value_{i}">Value {i}</a></td></tr>"#
+        )
+        .unwrap();
+    }
+    s.push_str("</tbody></table>");
+    for i in 0..400 {
+        write!(
+            s,
+            r##"<h2 id="s{i}">Section {i}</h2><p>The <b>article</b> discusses <a href="/wiki/Topic_{i}">topic {i}</a>, which relates to <a href="/wiki/Topic_{j}#History">history</a> and <i>many</i> other subjects, as described by <a href="https://example.org/source/{i}?ref=wiki">sources</a>.<sup><a href="#cite-{i}">[{i}]</a></sup> It continues with more text about the subject, so paragraphs are as long as they usually are.</p>"##,
+            j = i / 2
+        )
+        .unwrap();
+    }
+    s.push_str(r#"<ol class="references">"#);
+    for i in 0..400 {
+        write!(
+            s,
+            r#"<li id="cite-{i}"><cite><a href="https://example.org/book/{i}">Book {i}</a></cite>, p. {i}.</li>"#
+        )
+        .unwrap();
+    }
+    s.push_str("</ol></div><script>(function () { mw.loader.load(['site', 'skin']); })();</script></body></html>");
+    s
+}
+
+fn parse(html: &str) -> RcDom {
+    html5ever::parse_document(RcDom::default(), Default::default())
+        .from_utf8()
+        .read_from(&mut html.as_bytes())
+        .unwrap()
+}
+
+fn bench_page(c: &mut Criterion) {
+    let html = article();
+    let config = Config::new(Regex::new("article").unwrap());
+    let origin = Url::parse("https://en.wikipedia.org/wiki/Article").unwrap();
+
+    let mut group = c.benchmark_group("page");
+    // Pages per second
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("parse", |b| b.iter(|| parse(&html)));
+    group.bench_function("inner_text", |b| {
+        b.iter_batched_ref(
+            || parse(&html),
+            |dom| webgrep::inner_text(dom, &config),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("links", |b| {
+        b.iter_batched_ref(
+            || parse(&html),
+            |dom| webgrep::links(&origin, dom, &config),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_page);
+criterion_main!(benches);
@@ -1,70 +1,1691 @@
-use clap::Parser;
-use regex::{Regex, RegexBuilder};
+use clap::{ArgEnum, ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueSource};
+use regex::Regex;
 use reqwest::Url;
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeSet;
+use std::io::{IsTerminal, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::time::Duration;
+use webgrep::cache::Cache;
+use webgrep::client::Response;
+use webgrep::pattern::PatternEngine;
 
 #[derive(Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Read default options from TOML file FILE,
+    /// instead of `~/.config/webgrep/config.toml`
+    #[clap(long, value_name = "FILE", env = "WEBGREP_CONFIG")]
+    config: Option<PathBuf>,
+
     /// Regex pattern to search for
     #[clap(required = true, value_name = "PATTERN")]
-    search_re: Regex,
+    search_re: Option<String>,
 
     /// URIs to start search from
-    #[clap(multiple_occurrences = true, required = true, value_name = "URL")]
+    #[clap(
+        multiple_occurrences = true,
+        required_unless_present = "url-depths",
+        value_name = "URL"
+    )]
     urls: Vec<Url>,
 
-    /// Limit search depth to NUM links from starting URL
-    #[clap(short = 'd', long, default_value_t = 1, value_name = "NUM")]
-    max_depth: u64,
+    /// Also start search from URL,
+    /// as if it were DEPTH links from a starting URL
+    #[clap(
+        long = "url-depth",
+        multiple_occurrences = true,
+        value_name = "URL=DEPTH",
+        env = "WEBGREP_URL_DEPTH"
+    )]
+    url_depths: Vec<UrlDepth>,
+
+    /// Limit search depth to NUM links from starting URL,
+    /// only search starting URLs if 0,
+    /// or follow links without limit if `unlimited` or -1
+    #[clap(
+        short = 'd',
+        long,
+        default_value = "1",
+        allow_hyphen_values = true,
+        value_name = "NUM",
+        env = "WEBGREP_MAX_DEPTH"
+    )]
+    max_depth: MaxDepth,
+
+    /// Skip links with more than N path segments,
+    /// like 4 for `/a/b/c/d`
+    #[clap(long, value_name = "N", env = "WEBGREP_MAX_URL_DEPTH")]
+    max_url_depth: Option<usize>,
+
+    /// Wait at least MS milliseconds between requests to a host
+    #[clap(
+        long,
+        default_value_t = 1000,
+        value_name = "MS",
+        env = "WEBGREP_MIN_DELAY_MS"
+    )]
+    min_delay_ms: u64,
+
+    /// Wait a random time up to MS milliseconds between requests to a host,
+    /// instead of exactly --min-delay-ms
+    #[clap(long, value_name = "MS", env = "WEBGREP_MAX_DELAY_MS")]
+    max_delay_ms: Option<u64>,
+
+    /// Wait exactly MS milliseconds between requests to HOST,
+    /// instead of --min-delay-ms
+    #[clap(
+        long = "host-delay",
+        multiple_occurrences = true,
+        value_name = "HOST=MS",
+        env = "WEBGREP_HOST_DELAY"
+    )]
+    host_delays: Vec<HostDelay>,
+
+    /// Wait at least the `Crawl-delay` in each host's robots.txt
+    /// between requests to it
+    #[clap(long, env = "WEBGREP_RESPECT_CRAWL_DELAY")]
+    respect_crawl_delay: bool,
+
+    /// Request each page with HEAD before GET,
+    /// skip GET if HEAD fails,
+    /// except with 405 Method Not Allowed or 501 Not Implemented,
+    /// and print pages that only answer GET after searching
+    #[clap(long, env = "WEBGREP_PROBE_METHODS")]
+    probe_methods: bool,
+
+    /// Make at most RPS requests per second to all hosts
+    #[clap(long, value_name = "RPS", env = "WEBGREP_RATE_LIMIT")]
+    rate_limit: Option<f64>,
+
+    /// Start queued pages and requests
+    /// with the fewest links from a starting URL first,
+    /// the most links first,
+    /// or by URL
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "deep-first",
+        value_name = "ORDER",
+        env = "WEBGREP_PRIORITY"
+    )]
+    priority: Priority,
+
+    /// Parse and search at most N pages at once,
+    /// instead of one per CPU,
+    /// independent of how many requests are in flight
+    #[clap(long, value_name = "N", env = "WEBGREP_WORKERS")]
+    workers: Option<NonZeroUsize>,
+
+    /// Keep at most N pages waiting to be searched,
+    /// dropping those last in --priority order
+    #[clap(long, value_name = "N", env = "WEBGREP_QUEUE_LIMIT")]
+    queue_limit: Option<usize>,
+
+    /// Search bodies of these media types as plain text,
+    /// like `text/csv`
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "MIME,...",
+        env = "WEBGREP_PLAIN_TYPES"
+    )]
+    plain_types: Vec<String>,
+
+    /// Skip bodies of these media types
+    /// without reading them
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "MIME,...",
+        env = "WEBGREP_SKIP_TYPES"
+    )]
+    skip_types: Vec<String>,
+
+    /// Fail bodies longer than N bytes,
+    /// without reading the rest
+    #[clap(
+        long,
+        value_name = "N",
+        default_value_t = webgrep::client::BODY_SIZE_LIMIT,
+        env = "WEBGREP_MAX_RESPONSE_SIZE_BYTES"
+    )]
+    max_response_size_bytes: u64,
+
+    /// Also trust CA certificates in PEM FILE,
+    /// like a private corporate CA
+    #[clap(long, value_name = "FILE", env = "WEBGREP_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+
+    /// Authenticate with the client certificate in PEM FILE,
+    /// for mutual TLS
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "client-key",
+        env = "WEBGREP_CLIENT_CERT"
+    )]
+    client_cert: Option<PathBuf>,
+
+    /// Authenticate with the PKCS #8 private key in PEM FILE,
+    /// for `--client-cert`
+    #[clap(
+        long,
+        value_name = "FILE",
+        requires = "client-cert",
+        env = "WEBGREP_CLIENT_KEY"
+    )]
+    client_key: Option<PathBuf>,
+
+    /// Keep at most N idle connections to each host
+    #[clap(long, value_name = "N", env = "WEBGREP_CONNECTION_POOL_SIZE")]
+    connection_pool_size: Option<usize>,
+
+    /// Only connect to IPv4 addresses
+    #[clap(short = '4', long, conflicts_with = "ipv6", env = "WEBGREP_IPV4")]
+    ipv4: bool,
+
+    /// Only connect to IPv6 addresses
+    #[clap(short = '6', long, env = "WEBGREP_IPV6")]
+    ipv6: bool,
+
+    /// Also require regex pattern PATTERN to match
+    #[clap(
+        short = 'e',
+        long = "pattern",
+        multiple_occurrences = true,
+        value_name = "PATTERN",
+        env = "WEBGREP_PATTERN"
+    )]
+    patterns: Vec<String>,
+
+    /// Also require regex pattern in FILE to match
+    #[clap(long, value_name = "FILE", env = "WEBGREP_PATTERN_FILE")]
+    pattern_file: Option<PathBuf>,
+
+    /// Match pages matching any pattern, instead of all patterns
+    #[clap(long, env = "WEBGREP_ANY_PATTERN")]
+    any_pattern: bool,
+
+    /// Find pages that don't match
+    #[clap(short = 'v', long, env = "WEBGREP_INVERT_MATCH")]
+    invert_match: bool,
+
+    /// Print each match of PATTERN replaced with TEMPLATE,
+    /// which may refer to capture groups like `$1`
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with = "invert-match",
+        env = "WEBGREP_REPLACE"
+    )]
+    replace: Option<String>,
+
+    /// Print the text captured by group `(?P<NAME>...)` of PATTERN
+    /// for each match,
+    /// instead of the path to its page
+    #[clap(
+        long,
+        value_name = "NAME",
+        conflicts_with_all = &["invert-match", "replace"],
+        env = "WEBGREP_EXTRACT"
+    )]
+    extract: Option<String>,
+
+    /// Print each value from `--extract` once,
+    /// across all pages
+    #[clap(long, requires = "extract", env = "WEBGREP_UNIQUE_EXTRACTS")]
+    unique_extracts: bool,
+
+    /// Color matches
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "auto",
+        value_name = "WHEN",
+        env = "WEBGREP_COLOR"
+    )]
+    color: ColorChoice,
+
+    /// Print each match of PATTERN
+    #[clap(
+        short = 'o',
+        long,
+        conflicts_with_all = &["invert-match", "replace", "extract"],
+        env = "WEBGREP_ONLY_MATCHING"
+    )]
+    only_matching: bool,
+
+    /// Print at most N matches from each page,
+    /// with `--only-matching`, `--replace`, or `--extract`
+    #[clap(
+        short = 'm',
+        long,
+        value_name = "N",
+        env = "WEBGREP_MAX_MATCHES_PER_PAGE"
+    )]
+    max_matches_per_page: Option<NonZeroUsize>,
+
+    /// Print each match as TEMPLATE,
+    /// replacing `{url}`, `{path}`, `{depth}`, `{match}`, and `{latency_ms}`,
+    /// where `{latency_ms}` is empty for cached pages
+    #[clap(long, value_name = "TEMPLATE", env = "WEBGREP_FORMAT")]
+    format: Option<String>,
+
+    /// Print each link found on a page with links to follow,
+    /// like `SOURCE<TAB>TARGET`,
+    /// instead of matches
+    #[clap(long, env = "WEBGREP_LIST_LINKS")]
+    list_links: bool,
 
     /// Search case insensitively
-    #[clap(short = 'i', long)]
+    #[clap(short = 'i', long, env = "WEBGREP_IGNORE_CASE")]
     ignore_case: bool,
 
+    /// Let `.` match newlines,
+    /// so patterns can span lines
+    #[clap(short = 'U', long, env = "WEBGREP_MULTILINE")]
+    multiline: bool,
+
+    /// Only match whole words,
+    /// so `cat` doesn't match `concatenate`
+    #[clap(short = 'w', long, env = "WEBGREP_WORD_REGEXP")]
+    word_regexp: bool,
+
+    /// Search for patterns as literal strings,
+    /// not regexes
+    #[clap(short = 'F', long, env = "WEBGREP_FIXED_STRINGS")]
+    fixed_strings: bool,
+
     /// Exclude URLs matching regex pattern
-    #[clap(long, value_name = "PATTERN")]
+    #[clap(long, value_name = "PATTERN", env = "WEBGREP_EXCLUDE_URLS_RE")]
     exclude_urls_re: Option<Regex>,
+
+    /// Follow links to known analytics and ad-tracking domains
+    #[clap(long, env = "WEBGREP_NO_DOMAIN_BLOCKLIST")]
+    no_domain_blocklist: bool,
+
+    /// Skip links to DOMAIN and its subdomains
+    #[clap(long, value_name = "DOMAIN", env = "WEBGREP_BLOCK_DOMAIN")]
+    block_domain: Vec<String>,
+
+    /// Ignore text inside HTML tags named TAG
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        default_value = "head,script",
+        value_name = "TAG,...",
+        env = "WEBGREP_EXCLUDE_TAGS"
+    )]
+    exclude_tags: Vec<String>,
+
+    /// Only search text inside HTML tags named TAG
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "TAG,...",
+        env = "WEBGREP_INCLUDE_TAGS"
+    )]
+    include_tags: Vec<String>,
+
+    /// Also search values of HTML attributes named ATTR,
+    /// like `alt`,
+    /// or `content`,
+    /// with `--exclude-tags script`,
+    /// for `<meta>` in `<head>`
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "ATTR,...",
+        env = "WEBGREP_INCLUDE_HTML_ATTRS"
+    )]
+    include_html_attrs: Vec<String>,
+
+    /// Ignore blocks of HTML that are mostly links,
+    /// like navigation bars and footers,
+    /// to only search page content
+    #[clap(long, env = "WEBGREP_READABILITY")]
+    readability: bool,
+
+    /// Search only the title, author, subject, and keywords of PDFs,
+    /// not their text
+    #[clap(long, env = "WEBGREP_PDF_METADATA_ONLY")]
+    pdf_metadata_only: bool,
+
+    /// Open encrypted PDFs with PASS,
+    /// instead of an empty password
+    #[clap(long, value_name = "PASS", env = "WEBGREP_PDF_PASSWORD")]
+    pdf_password: Option<String>,
+
+    /// Remove query parameters from discovered URLs
+    #[clap(long, env = "WEBGREP_STRIP_QUERY")]
+    strip_query: bool,
+
+    /// Remove query parameters named PARAM from discovered URLs
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "PARAM,...",
+        env = "WEBGREP_STRIP_PARAMS"
+    )]
+    strip_params: Vec<String>,
+
+    /// Visit each URL at most once
+    #[clap(long, env = "WEBGREP_SKIP_VISITED")]
+    skip_visited: bool,
+
+    /// Remember visited URLs exactly, instead of with a Bloom filter, for --skip-visited
+    #[clap(long, env = "WEBGREP_EXACT_VISITED")]
+    exact_visited: bool,
+
+    /// Expected number of URLs to visit, for --skip-visited
+    #[clap(
+        long,
+        default_value_t = 1_000_000,
+        value_name = "NUM",
+        env = "WEBGREP_BLOOM_CAPACITY"
+    )]
+    bloom_capacity: usize,
+
+    /// Probability of skipping an unvisited URL, for --skip-visited
+    #[clap(
+        long,
+        default_value_t = 0.01,
+        value_name = "RATE",
+        env = "WEBGREP_BLOOM_FP_RATE"
+    )]
+    bloom_fp_rate: f64,
+
+    /// Also follow URLs in stylesheets
+    #[clap(long, env = "WEBGREP_FOLLOW_CSS_LINKS")]
+    follow_css_links: bool,
+
+    /// Also follow URLs of scripts, images, and other embedded resources
+    #[clap(long, env = "WEBGREP_FOLLOW_RESOURCE_LINKS")]
+    follow_resource_links: bool,
+
+    /// Also search scripts, for --follow-resource-links
+    #[clap(long, env = "WEBGREP_SEARCH_SCRIPTS")]
+    search_scripts: bool,
+
+    /// Search again every SECS seconds,
+    /// printing new matches with `+`
+    /// and disappeared matches with `-`
+    #[clap(long, value_name = "SECS", env = "WEBGREP_WATCH")]
+    watch: Option<u64>,
+
+    /// Print matches not in FILE with `+`
+    /// and matches in FILE but not found with `-`,
+    /// where FILE is output from a previous search
+    #[clap(long, value_name = "FILE", env = "WEBGREP_DIFF_AGAINST")]
+    diff_against: Option<PathBuf>,
+
+    /// Search string values of JSON responses,
+    /// instead of raw JSON
+    #[clap(long, env = "WEBGREP_SEARCH_JSON_VALUES")]
+    search_json_values: bool,
+
+    /// Search keys of JSON responses,
+    /// instead of raw JSON
+    #[clap(long, env = "WEBGREP_SEARCH_JSON_KEYS")]
+    search_json_keys: bool,
+
+    /// Write an HTML report of the search to FILE
+    #[clap(long, value_name = "FILE", env = "WEBGREP_HTML_REPORT")]
+    html_report: Option<PathBuf>,
+
+    /// Print searched pages as an indented tree after searching,
+    /// marking matching pages with `[matched]`
+    #[clap(long, env = "WEBGREP_TREE_OUTPUT")]
+    tree_output: bool,
+
+    /// Search only cached pages,
+    /// and print URLs that would be requested,
+    /// without making requests
+    #[clap(long, env = "WEBGREP_DRY_RUN")]
+    dry_run: bool,
+
+    /// Print links skipped for their scheme,
+    /// like `mailto:`,
+    /// to stderr
+    #[clap(long, env = "WEBGREP_VERBOSE")]
+    verbose: bool,
+
+    /// Print errors to stderr as prose,
+    /// or as a JSON object per line,
+    /// including pages that failed to load
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "text",
+        value_name = "FORMAT",
+        env = "WEBGREP_ERROR_FORMAT"
+    )]
+    error_format: ErrorFormat,
+
+    /// Also write matches to the Unix domain socket at PATH
+    #[clap(long, value_name = "PATH", env = "WEBGREP_SOCKET")]
+    socket: Option<PathBuf>,
+
+    /// Post each match as JSON to URL,
+    /// instead of printing it
+    #[clap(long, value_name = "URL", env = "WEBGREP_WEBHOOK")]
+    webhook: Option<Url>,
+
+    /// Send VALUE as the `Authorization` header to --webhook,
+    /// like `Bearer TOKEN`
+    #[clap(
+        long,
+        requires = "webhook",
+        value_name = "VALUE",
+        env = "WEBGREP_WEBHOOK_AUTH_HEADER"
+    )]
+    webhook_auth_header: Option<String>,
+
+    /// Write pages and matches to a SQLite database at FILE
+    #[clap(long, value_name = "FILE", env = "WEBGREP_SQLITE")]
+    sqlite: Option<PathBuf>,
+
+    /// Print links that returned an HTTP error status,
+    /// with the path to each,
+    /// after searching
+    #[clap(long, env = "WEBGREP_DEAD_LINKS")]
+    dead_links: bool,
+
+    /// Write links that returned an HTTP error status to FILE
+    #[clap(long, value_name = "FILE", env = "WEBGREP_DEAD_LINKS_OUTPUT")]
+    dead_links_output: Option<PathBuf>,
+
+    /// Store cached pages in a file per page,
+    /// in one embedded database,
+    /// or in memory,
+    /// for only this run
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "file",
+        value_name = "BACKEND",
+        env = "WEBGREP_CACHE_BACKEND"
+    )]
+    cache_backend: CacheBackend,
+
+    /// Encode cached pages as bincode,
+    /// MessagePack,
+    /// or JSON,
+    /// see `wg cache migrate`
+    #[clap(
+        long,
+        arg_enum,
+        default_value = "bincode",
+        value_name = "FORMAT",
+        env = "WEBGREP_CACHE_FORMAT"
+    )]
+    cache_format: CacheFormat,
+
+    /// Compress cached pages with zstd
+    #[clap(long, env = "WEBGREP_COMPRESS_CACHE")]
+    compress_cache: bool,
+
+    /// Print counts of pages, errors, and matches,
+    /// and the time taken,
+    /// to stderr after searching
+    #[clap(long, env = "WEBGREP_STATS")]
+    stats: bool,
+
+    /// Print cache hits, misses, and errors to stderr after searching
+    #[clap(long, env = "WEBGREP_CACHE_STATS")]
+    cache_stats: bool,
+
+    /// Warn when pages at different URLs have the same text
+    #[clap(long, env = "WEBGREP_DETECT_DUPLICATES")]
+    detect_duplicates: bool,
+
+    /// Also follow URLs in `srcset` attributes
+    #[clap(long, env = "WEBGREP_FOLLOW_SRCSET")]
+    follow_srcset: bool,
+
+    /// Also follow URLs in attributes named NAME, like `data-src`
+    #[clap(
+        long = "link-attr",
+        multiple_occurrences = true,
+        value_name = "NAME",
+        env = "WEBGREP_LINK_ATTR"
+    )]
+    link_attrs: Vec<String>,
+
+    /// Also follow alternate versions of pages in languages LANG,
+    /// from `<link rel="alternate" hreflang="LANG">`,
+    /// where `es` also matches regions like `es-MX`
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        value_name = "LANG,...",
+        env = "WEBGREP_FOLLOW_HREFLANG"
+    )]
+    follow_hreflang: Vec<String>,
+}
+
+#[derive(Clone, ArgEnum)]
+enum CacheBackend {
+    File,
+    Sled,
+    Memory,
+}
+
+#[derive(Clone, Copy, ArgEnum)]
+enum CacheFormat {
+    Bincode,
+    Msgpack,
+    Json,
+}
+
+impl From<CacheFormat> for webgrep::cache::Format {
+    fn from(x: CacheFormat) -> Self {
+        match x {
+            CacheFormat::Bincode => webgrep::cache::Format::Bincode,
+            CacheFormat::Msgpack => webgrep::cache::Format::MessagePack,
+            CacheFormat::Json => webgrep::cache::Format::Json,
+        }
+    }
+}
+
+#[derive(Clone, ArgEnum)]
+enum Priority {
+    ShallowFirst,
+    DeepFirst,
+    Alphabetical,
+}
+
+#[derive(Clone, ArgEnum)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, ArgEnum)]
+enum ColorChoice {
+    Always,
+    Never,
+    Auto,
+}
+
+struct MaxDepth(Option<u64>);
+
+impl FromStr for MaxDepth {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unlimited" | "-1" => Ok(Self(None)),
+            s => s.parse().map(|x| Self(Some(x))),
+        }
+    }
+}
+
+struct UrlDepth(Url, u64);
+
+impl FromStr for UrlDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // URLs may contain `=`,
+        // but depths don't.
+        let (url, depth) = s
+            .rsplit_once('=')
+            .ok_or_else(|| "expected URL=DEPTH".to_owned())?;
+        Ok(Self(
+            url.parse().map_err(|e| format!("invalid URL: {}", e))?,
+            depth.parse().map_err(|e| format!("invalid DEPTH: {}", e))?,
+        ))
+    }
+}
+
+struct HostDelay(String, Duration);
+
+impl FromStr for HostDelay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, ms) = s
+            .split_once('=')
+            .ok_or_else(|| "expected HOST=MS".to_owned())?;
+        ms.parse()
+            .map(|ms| Self(host.to_owned(), Duration::from_millis(ms)))
+            .map_err(|e| format!("invalid MS: {}", e))
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a shell completion script
+    Completions {
+        #[clap(arg_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Manage cached pages
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Write cached pages to a gzipped tar archive at FILE
+    Export {
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Export pages encoded as FORMAT
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "bincode",
+            value_name = "FORMAT",
+            env = "WEBGREP_CACHE_FORMAT"
+        )]
+        cache_format: CacheFormat,
+
+        /// Export pages cached in a file per page
+        /// or in one embedded database
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "file",
+            value_name = "BACKEND",
+            env = "WEBGREP_CACHE_BACKEND"
+        )]
+        cache_backend: CacheBackend,
+    },
+    /// Add pages from a gzipped tar archive at FILE,
+    /// written by `wg cache export`,
+    /// to the cache
+    Import {
+        #[clap(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Replace pages already in the cache
+        #[clap(long)]
+        overwrite: bool,
+
+        /// Compress imported pages with zstd
+        #[clap(long, env = "WEBGREP_COMPRESS_CACHE")]
+        compress_cache: bool,
+
+        /// Import pages encoded as FORMAT
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "bincode",
+            value_name = "FORMAT",
+            env = "WEBGREP_CACHE_FORMAT"
+        )]
+        cache_format: CacheFormat,
+
+        /// Import pages to be cached in a file per page
+        /// or in one embedded database
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "file",
+            value_name = "BACKEND",
+            env = "WEBGREP_CACHE_BACKEND"
+        )]
+        cache_backend: CacheBackend,
+    },
+    /// Rewrite cached pages encoded as FROM
+    /// to be encoded as TO
+    Migrate {
+        #[clap(arg_enum, value_name = "FROM")]
+        from: CacheFormat,
+
+        #[clap(arg_enum, value_name = "TO")]
+        to: CacheFormat,
+
+        /// Compress migrated pages with zstd
+        #[clap(long, env = "WEBGREP_COMPRESS_CACHE")]
+        compress_cache: bool,
+
+        /// Migrate pages cached in a file per page
+        /// or in one embedded database
+        #[clap(
+            long,
+            arg_enum,
+            default_value = "file",
+            value_name = "BACKEND",
+            env = "WEBGREP_CACHE_BACKEND"
+        )]
+        cache_backend: CacheBackend,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    let args = parse_args()?;
 
-    webgrep::run(
-        std::io::BufWriter::new(std::io::stdout()),
-        indicatif::MultiProgress::new(),
-        mk_static(
-            webgrep::cache::FileCache::new("page-cache")
+    match args.command {
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Args::command(), "wg", &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Cache { command }) => return run_cache_command(command).await,
+        None => {}
+    }
+
+    // Patterns are compiled here,
+    // not when parsing arguments,
+    // because flags like `--fixed-strings` change them.
+    let flags = PatternFlags {
+        ignore_case: args.ignore_case,
+        multiline: args.multiline,
+        word_regexp: args.word_regexp,
+        fixed_strings: args.fixed_strings,
+    };
+    // `PATTERN` is required without a subcommand.
+    let search_re = args.search_re.unwrap();
+    let search_re = build_re(&search_re, &flags)
+        .unwrap_or_else(|e| invalid_pattern("<PATTERN>", &search_re, e));
+    if let Some(name) = &args.extract {
+        if !search_re.has_group(name) {
+            return Err(format!("PATTERN has no capture group named `{}`", name).into());
+        }
+    }
+    let mut patterns: Vec<_> = args
+        .patterns
+        .iter()
+        .map(|x| {
+            build_re(x, &flags).unwrap_or_else(|e| invalid_pattern("--pattern <PATTERN>", x, e))
+        })
+        .collect();
+    if let Some(path) = args.pattern_file {
+        patterns.push(read_pattern_file(&path, &flags)?);
+    }
+
+    // Pages must be fetched again
+    // to see if they changed.
+    let watch = args.watch.map(Duration::from_secs);
+    let cache: Box<dyn Cache<Url, Response> + Send + Sync> = match args.cache_backend {
+        CacheBackend::File => {
+            let cache = webgrep::cache::FileCache::new("page-cache")
                 .await
-                .expect("Failed to initialize cache"),
-        ),
-        mk_static(
-            reqwest::Client::builder()
-                // `timeout` doesn't work without `connect_timeout`.
-                .connect_timeout(core::time::Duration::from_secs(60))
-                .timeout(core::time::Duration::from_secs(60))
-                .build()
-                .expect("Failed to initialize web client"),
-        ),
-        Duration::from_secs(1),
-        // Tokio uses number of CPU cores as default number of worker threads.
-        // `tokio::runtime::Handle::current().metrics().num_workers()`
-        // is only available in unstable Tokio.
-        // A larger buffer isn't necessary faster.
-        NonZeroUsize::new(num_cpus::get()).unwrap_or(NonZeroUsize::new(1).unwrap()),
-        mk_static(args.exclude_urls_re),
-        args.max_depth,
-        mk_static(
-            RegexBuilder::new(args.search_re.as_str())
-                .case_insensitive(args.ignore_case)
-                .build()
-                .unwrap(),
+                .expect("Failed to initialize cache")
+                .with_format(args.cache_format.into())
+                .with_compression(args.compress_cache);
+            match watch {
+                Some(interval) => Box::new(cache.with_ttl(interval)),
+                None => Box::new(cache),
+            }
+        }
+        CacheBackend::Sled => {
+            let cache = webgrep::cache::SledCache::new("page-db")
+                .expect("Failed to initialize cache")
+                .with_format(args.cache_format.into())
+                .with_compression(args.compress_cache);
+            match watch {
+                Some(interval) => Box::new(cache.with_ttl(interval)),
+                None => Box::new(cache),
+            }
+        }
+        CacheBackend::Memory => {
+            let cache = webgrep::cache::MemoryCache::new();
+            match watch {
+                Some(interval) => Box::new(cache.with_ttl(interval)),
+                None => Box::new(cache),
+            }
+        }
+    };
+    let cache = Arc::new(cache);
+    let mut client = reqwest::Client::builder()
+        // `timeout` doesn't work without `connect_timeout`.
+        .connect_timeout(core::time::Duration::from_secs(60))
+        .timeout(core::time::Duration::from_secs(60));
+    if let Some(n) = args.connection_pool_size {
+        client = client.pool_max_idle_per_host(n);
+    }
+    if let Some(path) = &args.ca_cert {
+        for cert in read_ca_certs(path)? {
+            client = client.add_root_certificate(cert);
+        }
+    }
+    if let (Some(cert), Some(key)) = (&args.client_cert, &args.client_key) {
+        client = client.identity(read_identity(cert, key)?);
+    }
+    // Binding to an unspecified address of one family
+    // only connects to addresses of that family.
+    if args.ipv4 {
+        client = client.local_address(IpAddr::from(Ipv4Addr::UNSPECIFIED));
+    } else if args.ipv6 {
+        client = client.local_address(IpAddr::from(Ipv6Addr::UNSPECIFIED));
+    }
+    let client = mk_static(
+        webgrep::client::HttpClient::new(client.build().expect("Failed to initialize web client"))
+            .with_plain_types(args.plain_types)
+            .with_skip_types(args.skip_types)
+            .with_max_body_size(args.max_response_size_bytes),
+    );
+    let mut urls = args.urls;
+    for UrlDepth(url, _) in &args.url_depths {
+        if !urls.contains(url) {
+            urls.push(url.clone());
+        }
+    }
+
+    if args.max_delay_ms.is_some_and(|x| x < args.min_delay_ms) {
+        return Err("--max-delay-ms must be at least --min-delay-ms".into());
+    }
+    if args.rate_limit.is_some_and(|x| x.is_nan() || x <= 0.0) {
+        return Err("--rate-limit must be positive".into());
+    }
+    let default_config = webgrep::Config::new(search_re);
+    let config = mk_static(webgrep::Config {
+        rate_limit: args.rate_limit,
+        node_priority: match args.priority {
+            Priority::ShallowFirst => webgrep::NodePriority::ShallowFirst,
+            Priority::DeepFirst => webgrep::NodePriority::DeepFirst,
+            Priority::Alphabetical => webgrep::NodePriority::Alphabetical,
+        },
+        page_threads: args.workers.unwrap_or(default_config.page_threads),
+        queue_limit: args.queue_limit,
+        request_delay: Duration::from_millis(args.min_delay_ms),
+        max_request_delay: args.max_delay_ms.map(Duration::from_millis),
+        seed_depths: args
+            .url_depths
+            .iter()
+            .map(|UrlDepth(url, depth)| (url.clone(), *depth))
+            .collect(),
+        host_delays: args
+            .host_delays
+            .into_iter()
+            .map(|HostDelay(host, delay)| (host, delay))
+            .collect(),
+        respect_crawl_delay: args.respect_crawl_delay,
+        probe_methods: args.probe_methods,
+        exclude_urls_re: args.exclude_urls_re,
+        blocked_domains: if args.no_domain_blocklist {
+            args.block_domain
+        } else {
+            default_config
+                .blocked_domains
+                .into_iter()
+                .chain(args.block_domain)
+                .collect()
+        },
+        max_depth: args.max_depth.0,
+        max_url_depth: args.max_url_depth,
+        exclude_tags: args.exclude_tags,
+        include_tags: args.include_tags,
+        include_html_attrs: args.include_html_attrs,
+        readability: args.readability,
+        pdf_metadata_only: args.pdf_metadata_only,
+        pdf_password: args.pdf_password,
+        strip_query: args.strip_query,
+        strip_params: args.strip_params,
+        skip_visited: args.skip_visited,
+        exact_visited: args.exact_visited,
+        follow_css_links: args.follow_css_links,
+        follow_resource_links: args.follow_resource_links,
+        search_scripts: args.search_scripts,
+        search_json_values: args.search_json_values,
+        search_json_keys: args.search_json_keys,
+        follow_srcset: args.follow_srcset,
+        link_attrs: args.link_attrs,
+        follow_hreflang: args.follow_hreflang,
+        list_links: args.list_links,
+        bloom_capacity: args.bloom_capacity,
+        bloom_fp_rate: args.bloom_fp_rate,
+        record_crawl: args.html_report.is_some() || args.sqlite.is_some() || args.tree_output,
+        verbose: args.verbose,
+        error_format: match args.error_format {
+            ErrorFormat::Text => webgrep::ErrorFormat::Text,
+            ErrorFormat::Json => webgrep::ErrorFormat::Json,
+        },
+        dry_run: args.dry_run,
+        webhook: args.webhook.map(|url| webgrep::Webhook {
+            url,
+            authorization: args.webhook_auth_header,
+        }),
+        record_dead_links: args.dead_links || args.dead_links_output.is_some(),
+        detect_duplicates: args.detect_duplicates,
+        extra_search_res: patterns,
+        any_pattern: args.any_pattern,
+        invert_match: args.invert_match,
+        replace: args.replace,
+        extract: args.extract,
+        unique_extracts: args.unique_extracts,
+        only_matching: args.only_matching,
+        max_matches_per_page: args.max_matches_per_page,
+        format: args.format,
+        // Colored matches wouldn't equal
+        // uncolored matches from a previous search.
+        color: args.diff_against.is_none()
+            && match args.color {
+                ColorChoice::Always => true,
+                ColorChoice::Never => false,
+                ColorChoice::Auto => std::io::stdout().is_terminal(),
+            },
+        ..default_config
+    });
+
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        config.shutdown.store(true, Ordering::Relaxed);
+        eprintln!("Finishing in-flight pages, press Ctrl-C again to quit now");
+        shutdown_signal().await;
+        std::process::exit(130);
+    });
+
+    let prev_matches = match &args.diff_against {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+                .lines()
+                .map(str::to_owned)
+                .collect(),
         ),
-        args.urls,
+        None => None,
+    };
+
+    let mut socket = match &args.socket {
+        Some(path) => Some(connect_socket(path).await?),
+        None => None,
+    };
+
+    let mut prev_matches = match (watch, prev_matches) {
+        (None, None) => {
+            let summary = webgrep::run(
+                Tee(std::io::BufWriter::new(std::io::stdout()), socket),
+                indicatif::MultiProgress::new(),
+                cache.clone(),
+                client,
+                config,
+                urls,
+            )
+            .await?;
+            print_summary(config, &summary, args.stats);
+            if args.cache_stats {
+                if let Some(stats) = cache.stats() {
+                    print_cache_stats(stats);
+                }
+            }
+            return write_reports(
+                &args.html_report,
+                &args.sqlite,
+                args.tree_output,
+                args.dead_links,
+                &args.dead_links_output,
+                &summary,
+            );
+        }
+        (_, prev_matches) => prev_matches.unwrap_or_default(),
+    };
+
+    loop {
+        let mut buffer = Vec::new();
+        let summary = webgrep::run(
+            &mut buffer,
+            indicatif::MultiProgress::new(),
+            cache.clone(),
+            client,
+            config,
+            urls.clone(),
+        )
+        .await?;
+        write_reports(
+            &args.html_report,
+            &args.sqlite,
+            args.tree_output,
+            args.dead_links,
+            &args.dead_links_output,
+            &summary,
+        )?;
+
+        let matches: BTreeSet<_> = String::from_utf8_lossy(&buffer)
+            .lines()
+            .map(str::to_owned)
+            .collect();
+        let mut stdout = Tee(std::io::stdout().lock(), socket.as_mut());
+        for x in matches.difference(&prev_matches) {
+            writeln!(stdout, "+ {}", x)?;
+        }
+        for x in prev_matches.difference(&matches) {
+            writeln!(stdout, "- {}", x)?;
+        }
+        stdout.flush()?;
+        prev_matches = matches;
+
+        let interrupted = print_summary(config, &summary, args.stats);
+        if args.cache_stats {
+            if let Some(stats) = cache.stats() {
+                print_cache_stats(stats);
+            }
+        }
+        if interrupted {
+            return Ok(());
+        }
+        match watch {
+            Some(interval) => tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = shutdown_signal() => return Ok(()),
+            },
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Connect to a Unix domain socket at `path`.
+#[cfg(unix)]
+async fn connect_socket(path: &Path) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+    let socket = tokio::net::UnixStream::connect(path)
+        .await
+        .map_err(|e| format!("Failed to connect to {}: {}", path.display(), e))?
+        .into_std()?;
+    // Matches are written synchronously.
+    socket.set_nonblocking(false)?;
+    Ok(Box::new(socket))
+}
+
+#[cfg(not(unix))]
+async fn connect_socket(_path: &Path) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+    Err("--socket is only supported on Unix".into())
+}
+
+/// Write to the first writer
+/// and the second,
+/// if any.
+struct Tee<A, B>(A, Option<B>);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.0.write(buf)?;
+        if let Some(x) = &mut self.1 {
+            x.write_all(&buf[..n])?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()?;
+        if let Some(x) = &mut self.1 {
+            x.flush()?;
+        }
+        Ok(())
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+                .expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+async fn run_cache_command(command: CacheCommand) -> Result<(), Box<dyn std::error::Error>> {
+    match command {
+        CacheCommand::Export {
+            file,
+            cache_format,
+            cache_backend,
+        } => {
+            let cache = open_cache(cache_backend, cache_format, false).await?;
+            let entries = cache.entries()?;
+            webgrep::cache::export(
+                std::io::BufWriter::new(
+                    std::fs::File::create(&file)
+                        .map_err(|e| format!("Failed to create {}: {}", file.display(), e))?,
+                ),
+                entries,
+            )?;
+            Ok(())
+        }
+        CacheCommand::Import {
+            file,
+            overwrite,
+            compress_cache,
+            cache_format,
+            cache_backend,
+        } => {
+            let cache = open_cache(cache_backend, cache_format, compress_cache).await?;
+            let entries: Vec<(Url, Response)> = webgrep::cache::import(std::io::BufReader::new(
+                std::fs::File::open(&file)
+                    .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?,
+            ))?;
+            for (k, v) in entries {
+                if overwrite || cache.get(&k).is_none() {
+                    cache.set(&k, &v)?;
+                }
+            }
+            Ok(())
+        }
+        CacheCommand::Migrate {
+            from,
+            to,
+            compress_cache,
+            cache_backend,
+        } => {
+            let entries = open_cache(cache_backend.clone(), from, false)
+                .await?
+                .entries()?;
+            // Entries are replaced in place,
+            // so a migration stopped part way
+            // leaves some entries in each format.
+            let cache = open_cache(cache_backend, to, compress_cache).await?;
+            for (k, v) in entries {
+                cache.set(&k, &v)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Open the persistent cache of pages in `backend`,
+/// encoded as `format`,
+/// and compressing written pages if `compress`.
+async fn open_cache(
+    backend: CacheBackend,
+    format: CacheFormat,
+    compress: bool,
+) -> Result<Box<dyn Cache<Url, Response> + Send + Sync>, Box<dyn std::error::Error>> {
+    match backend {
+        CacheBackend::File => Ok(Box::new(
+            webgrep::cache::FileCache::new("page-cache")
+                .await?
+                .with_format(format.into())
+                .with_compression(compress),
+        )),
+        CacheBackend::Sled => Ok(Box::new(
+            webgrep::cache::SledCache::new("page-db")?
+                .with_format(format.into())
+                .with_compression(compress),
+        )),
+        CacheBackend::Memory => Err("--cache-backend memory doesn't persist pages".into()),
+    }
+}
+
+/// Print statistics for a search to stderr,
+/// and return whether it was interrupted.
+/// Print whether the search was interrupted,
+/// and counts from `summary`
+/// if `stats`.
+fn print_summary(config: &webgrep::Config, summary: &webgrep::Summary, stats: bool) -> bool {
+    let interrupted = config.shutdown.load(Ordering::Relaxed);
+    if interrupted {
+        eprintln!("Interrupted");
+    }
+    if !stats {
+        return interrupted;
+    }
+    let secs = summary.elapsed.as_secs_f64();
+    eprintln!(
+        "pages: {}\ncache hits: {}\nerrors: {}\nmatches: {}\nelapsed: {:.2}s\nrequests/s: {:.2}",
+        summary.pages,
+        summary.cache_hits,
+        summary.errors,
+        summary.matches,
+        secs,
+        if secs > 0.0 {
+            summary.requests as f64 / secs
+        } else {
+            0.0
+        },
+    );
+    if config.queue_limit.is_some() {
+        eprintln!("dropped: {}", summary.dropped);
+    }
+    interrupted
+}
+
+fn print_cache_stats(stats: &webgrep::cache::CacheStats) {
+    let hits = stats.hits.load(Ordering::Relaxed);
+    let misses = stats.misses.load(Ordering::Relaxed);
+    let lookups = hits + misses;
+    eprintln!(
+        "cache hit rate: {:.2}%\ncache misses: {}\ncache errors: {}",
+        if lookups > 0 {
+            100.0 * hits as f64 / lookups as f64
+        } else {
+            0.0
+        },
+        misses,
+        stats.errors.load(Ordering::Relaxed),
+    );
+}
+
+fn write_reports(
+    html_report: &Option<PathBuf>,
+    sqlite: &Option<PathBuf>,
+    tree_output: bool,
+    dead_links: bool,
+    dead_links_output: &Option<PathBuf>,
+    summary: &webgrep::Summary,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(path) = html_report {
+        let mut report = String::new();
+        webgrep::report::write_html(&mut report, summary)?;
+        std::fs::write(path, report)?;
+    }
+
+    if let Some(path) = sqlite {
+        webgrep::report::write_sqlite(path, summary)?;
+    }
+
+    // Only a dry run records URLs it would fetch.
+    for url in &summary.would_fetch {
+        println!("would fetch: {}", url);
+    }
+
+    // Only probing methods records pages that refuse `HEAD`.
+    for url in &summary.get_only {
+        println!("GET only: {}", url);
+    }
+
+    if tree_output {
+        let mut tree = String::new();
+        webgrep::report::write_tree(&mut tree, summary)?;
+        print!("{}", tree);
+    }
+
+    if dead_links || dead_links_output.is_some() {
+        let mut report = String::new();
+        webgrep::report::write_dead_links(&mut report, summary)?;
+        if dead_links {
+            print!("{}", report);
+        }
+        if let Some(path) = dead_links_output {
+            std::fs::write(path, report)?;
+        }
+    }
+
+    Ok(())
+}
+
+struct PatternFlags {
+    ignore_case: bool,
+    multiline: bool,
+    word_regexp: bool,
+    fixed_strings: bool,
+}
+
+fn build_re(pattern: &str, flags: &PatternFlags) -> Result<PatternEngine, webgrep::pattern::Error> {
+    let pattern = if flags.fixed_strings {
+        regex::escape(pattern)
+    } else {
+        pattern.to_owned()
+    };
+    let pattern = if flags.word_regexp {
+        format!(r"\b(?:{})\b", pattern)
+    } else {
+        pattern
+    };
+    PatternEngine::with_flags(&pattern, flags.ignore_case, flags.multiline)
+}
+
+// Like the error `clap` gives
+// for values it fails to parse.
+fn invalid_pattern(arg: &str, pattern: &str, e: webgrep::pattern::Error) -> ! {
+    Args::command()
+        .error(
+            clap::ErrorKind::ValueValidation,
+            format!("Invalid value {:?} for '{}': {}", pattern, arg, e),
+        )
+        .exit()
+}
+
+fn read_pattern_file(path: &Path, flags: &PatternFlags) -> Result<PatternEngine, String> {
+    let pattern = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    build_re(pattern.trim(), flags)
+        .map_err(|e| format!("Invalid pattern in {}: {}", path.display(), e))
+}
+
+// A PEM file often bundles many certificates,
+// but `Certificate::from_pem` reads only the first.
+fn read_ca_certs(path: &Path) -> Result<Vec<reqwest::Certificate>, String> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let certs = pem
+        .split_inclusive(END)
+        .filter(|x| x.contains(END))
+        .map(|x| {
+            reqwest::Certificate::from_pem(x.as_bytes())
+                .map_err(|e| format!("Invalid certificate in {}: {}", path.display(), e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        Err(format!("No certificates in {}", path.display()))
+    } else {
+        Ok(certs)
+    }
+}
+
+fn read_identity(cert: &Path, key: &Path) -> Result<reqwest::Identity, String> {
+    let read = |path: &Path| {
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    };
+    reqwest::Identity::from_pkcs8_pem(&read(cert)?, &read(key)?).map_err(|e| {
+        format!(
+            "Invalid client certificate or key in {} and {}: {}",
+            cert.display(),
+            key.display(),
+            e
+        )
+    })
+}
+
+// Options in the config file act as default arguments.
+// Arguments from the command line
+// and environment variables
+// take precedence.
+fn parse_args() -> Result<Args, Box<dyn std::error::Error>> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    if args.command.is_some() {
+        return Ok(args);
+    }
+
+    let path = match &args.config {
+        Some(path) => path.clone(),
+        None => match directories::ProjectDirs::from("", "", "webgrep")
+            .map(|x| x.config_dir().join("config.toml"))
+            .filter(|x| x.exists())
+        {
+            Some(path) => path,
+            None => return Ok(args),
+        },
+    };
+    let config: ConfigFile = toml::from_str(
+        &std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
     )
-    .await
+    .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+    config.merge_into(&mut args, &matches);
+    Ok(args)
+}
+
+/// Options from the config file,
+/// keyed by long option name,
+/// like `Args`
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(rename = "url-depth", default, deserialize_with = "from_args")]
+    url_depths: Option<Vec<UrlDepth>>,
+    #[serde(default, deserialize_with = "from_arg")]
+    max_depth: Option<MaxDepth>,
+    max_url_depth: Option<usize>,
+    min_delay_ms: Option<u64>,
+    max_delay_ms: Option<u64>,
+    #[serde(rename = "host-delay", default, deserialize_with = "from_args")]
+    host_delays: Option<Vec<HostDelay>>,
+    respect_crawl_delay: Option<bool>,
+    probe_methods: Option<bool>,
+    rate_limit: Option<f64>,
+    #[serde(default, deserialize_with = "from_arg_enum")]
+    priority: Option<Priority>,
+    workers: Option<NonZeroUsize>,
+    queue_limit: Option<usize>,
+    plain_types: Option<Vec<String>>,
+    skip_types: Option<Vec<String>>,
+    max_response_size_bytes: Option<u64>,
+    ca_cert: Option<PathBuf>,
+    client_cert: Option<PathBuf>,
+    client_key: Option<PathBuf>,
+    connection_pool_size: Option<usize>,
+    ipv4: Option<bool>,
+    ipv6: Option<bool>,
+    #[serde(rename = "pattern")]
+    patterns: Option<Vec<String>>,
+    pattern_file: Option<PathBuf>,
+    any_pattern: Option<bool>,
+    invert_match: Option<bool>,
+    replace: Option<String>,
+    extract: Option<String>,
+    unique_extracts: Option<bool>,
+    #[serde(default, deserialize_with = "from_arg_enum")]
+    color: Option<ColorChoice>,
+    only_matching: Option<bool>,
+    max_matches_per_page: Option<NonZeroUsize>,
+    format: Option<String>,
+    list_links: Option<bool>,
+    ignore_case: Option<bool>,
+    multiline: Option<bool>,
+    word_regexp: Option<bool>,
+    fixed_strings: Option<bool>,
+    #[serde(default, deserialize_with = "from_arg")]
+    exclude_urls_re: Option<Regex>,
+    no_domain_blocklist: Option<bool>,
+    block_domain: Option<Vec<String>>,
+    exclude_tags: Option<Vec<String>>,
+    include_tags: Option<Vec<String>>,
+    include_html_attrs: Option<Vec<String>>,
+    readability: Option<bool>,
+    pdf_metadata_only: Option<bool>,
+    pdf_password: Option<String>,
+    strip_query: Option<bool>,
+    strip_params: Option<Vec<String>>,
+    skip_visited: Option<bool>,
+    exact_visited: Option<bool>,
+    bloom_capacity: Option<usize>,
+    bloom_fp_rate: Option<f64>,
+    follow_css_links: Option<bool>,
+    follow_resource_links: Option<bool>,
+    search_scripts: Option<bool>,
+    watch: Option<u64>,
+    diff_against: Option<PathBuf>,
+    search_json_values: Option<bool>,
+    search_json_keys: Option<bool>,
+    html_report: Option<PathBuf>,
+    tree_output: Option<bool>,
+    dry_run: Option<bool>,
+    verbose: Option<bool>,
+    #[serde(default, deserialize_with = "from_arg_enum")]
+    error_format: Option<ErrorFormat>,
+    socket: Option<PathBuf>,
+    webhook: Option<Url>,
+    webhook_auth_header: Option<String>,
+    sqlite: Option<PathBuf>,
+    dead_links: Option<bool>,
+    dead_links_output: Option<PathBuf>,
+    #[serde(default, deserialize_with = "from_arg_enum")]
+    cache_backend: Option<CacheBackend>,
+    #[serde(default, deserialize_with = "from_arg_enum")]
+    cache_format: Option<CacheFormat>,
+    compress_cache: Option<bool>,
+    stats: Option<bool>,
+    cache_stats: Option<bool>,
+    detect_duplicates: Option<bool>,
+    follow_srcset: Option<bool>,
+    #[serde(rename = "link-attr")]
+    link_attrs: Option<Vec<String>>,
+    follow_hreflang: Option<Vec<String>>,
+}
+
+impl ConfigFile {
+    /// Set each option in `args`
+    /// from this file,
+    /// unless `matches` has it
+    /// from the command line
+    /// or an environment variable.
+    fn merge_into(self, args: &mut Args, matches: &ArgMatches) {
+        // Clap names arguments by their fields in kebab case.
+        let is_unset = |field: &str| {
+            !matches!(
+                matches.value_source(field.replace('_', "-")),
+                Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+            )
+        };
+        macro_rules! merge {
+            ($($field:ident),* $(,)?) => {
+                $(
+                    if let Some(x) = self.$field {
+                        if is_unset(stringify!($field)) {
+                            args.$field = x.into();
+                        }
+                    }
+                )*
+            };
+        }
+        merge!(
+            url_depths,
+            max_depth,
+            max_url_depth,
+            min_delay_ms,
+            max_delay_ms,
+            host_delays,
+            respect_crawl_delay,
+            probe_methods,
+            rate_limit,
+            priority,
+            workers,
+            queue_limit,
+            plain_types,
+            skip_types,
+            max_response_size_bytes,
+            ca_cert,
+            client_cert,
+            client_key,
+            connection_pool_size,
+            ipv4,
+            ipv6,
+            patterns,
+            pattern_file,
+            any_pattern,
+            invert_match,
+            replace,
+            extract,
+            unique_extracts,
+            color,
+            only_matching,
+            max_matches_per_page,
+            format,
+            list_links,
+            ignore_case,
+            multiline,
+            word_regexp,
+            fixed_strings,
+            exclude_urls_re,
+            no_domain_blocklist,
+            block_domain,
+            exclude_tags,
+            include_tags,
+            include_html_attrs,
+            readability,
+            pdf_metadata_only,
+            pdf_password,
+            strip_query,
+            strip_params,
+            skip_visited,
+            exact_visited,
+            bloom_capacity,
+            bloom_fp_rate,
+            follow_css_links,
+            follow_resource_links,
+            search_scripts,
+            watch,
+            diff_against,
+            search_json_values,
+            search_json_keys,
+            html_report,
+            tree_output,
+            dry_run,
+            verbose,
+            error_format,
+            socket,
+            webhook,
+            webhook_auth_header,
+            sqlite,
+            dead_links,
+            dead_links_output,
+            cache_backend,
+            cache_format,
+            compress_cache,
+            stats,
+            cache_stats,
+            detect_duplicates,
+            follow_srcset,
+            link_attrs,
+            follow_hreflang,
+        );
+    }
+}
+
+/// A TOML string or number,
+/// for options parsed from text,
+/// like `max-depth = "unlimited"`
+/// or `max-depth = 2`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl Scalar {
+    fn parse<T: FromStr, E: serde::de::Error>(self) -> Result<T, E>
+    where
+        T::Err: std::fmt::Display,
+    {
+        match self {
+            Scalar::String(x) => x,
+            Scalar::Integer(x) => x.to_string(),
+            Scalar::Float(x) => x.to_string(),
+        }
+        .parse()
+        .map_err(E::custom)
+    }
+}
+
+/// Parse an option like on the command line.
+fn from_arg<'de, D: Deserializer<'de>, T: FromStr>(d: D) -> Result<Option<T>, D::Error>
+where
+    T::Err: std::fmt::Display,
+{
+    Scalar::deserialize(d)?.parse().map(Some)
+}
+
+/// Parse each value of an option like on the command line.
+fn from_args<'de, D: Deserializer<'de>, T: FromStr>(d: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    T::Err: std::fmt::Display,
+{
+    Vec::<Scalar>::deserialize(d)?
+        .into_iter()
+        .map(Scalar::parse)
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Parse an option with possible values like on the command line.
+fn from_arg_enum<'de, D: Deserializer<'de>, T: ArgEnum>(d: D) -> Result<Option<T>, D::Error> {
+    T::from_str(&String::deserialize(d)?, false)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
 }
 
 fn mk_static<T>(x: T) -> &'static T {
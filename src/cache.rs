@@ -1,64 +1,657 @@
+use base64::Engine;
+use dashmap::DashMap;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::task;
 
 pub trait Cache<K, V> {
     fn get(&self, k: &K) -> Option<V>;
-    fn set(&self, k: &K, v: &V) -> Result<(), Box<dyn std::error::Error>>;
+    fn set(&self, k: &K, v: &V) -> Result<(), Error>;
+
+    /// All fresh entries,
+    /// skipping entries that fail to read
+    fn entries(&self) -> Result<Vec<(K, V)>, Error>;
+
+    /// Counts of cache lookups and failures,
+    /// if this cache keeps them
+    fn stats(&self) -> Option<&CacheStats> {
+        None
+    }
+}
+
+// Lets callers choose a cache at runtime.
+impl<K, V, C: Cache<K, V> + ?Sized> Cache<K, V> for Box<C> {
+    fn get(&self, k: &K) -> Option<V> {
+        (**self).get(k)
+    }
+
+    fn set(&self, k: &K, v: &V) -> Result<(), Error> {
+        (**self).set(k, v)
+    }
+
+    fn entries(&self) -> Result<Vec<(K, V)>, Error> {
+        (**self).entries()
+    }
+
+    fn stats(&self) -> Option<&CacheStats> {
+        (**self).stats()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("failed to find home directory: {0}")]
+    Home(#[from] std::env::VarError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("failed to encode or decode bincode: {0}")]
+    Encode(#[from] bincode::Error),
+    #[error(transparent)]
+    Sled(#[from] sled::Error),
+    #[error("failed to encode or decode JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("failed to encode MessagePack: {0}")]
+    MessagePackEncode(#[from] rmp_serde::encode::Error),
+    #[error("failed to decode MessagePack: {0}")]
+    MessagePackDecode(#[from] rmp_serde::decode::Error),
+    #[error("invalid archive entry name `{0}`")]
+    EntryName(String),
+}
+
+/// How persistent caches encode entries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Bincode,
+    /// Faster than JSON,
+    /// and more compact than bincode
+    /// for text-heavy entries
+    MessagePack,
+    Json,
+}
+
+// Every zstd frame starts with these bytes,
+// and no entry in any format does,
+// so entries can be decompressed
+// whether or not they were compressed.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+impl Format {
+    /// Encode `x`,
+    /// compressed with zstd if `compress`.
+    fn encode(self, x: &impl serde::ser::Serialize, compress: bool) -> Result<Vec<u8>, Error> {
+        let x = match self {
+            Format::Bincode => bincode::serialize(x)?,
+            Format::MessagePack => rmp_serde::to_vec(x)?,
+            Format::Json => serde_json::to_vec(x)?,
+        };
+        if compress {
+            Ok(zstd::encode_all(x.as_slice(), 0)?)
+        } else {
+            Ok(x)
+        }
+    }
+
+    /// Decode `x`,
+    /// decompressing it first if necessary.
+    fn decode<T: serde::de::DeserializeOwned>(self, x: &[u8]) -> Result<T, Error> {
+        let decompressed;
+        let x = if x.starts_with(&ZSTD_MAGIC) {
+            decompressed = zstd::decode_all(x)?;
+            decompressed.as_slice()
+        } else {
+            x
+        };
+        match self {
+            Format::Bincode => Ok(bincode::deserialize(x)?),
+            Format::MessagePack => Ok(rmp_serde::from_slice(x)?),
+            Format::Json => Ok(serde_json::from_slice(x)?),
+        }
+    }
 }
 
 pub struct FileCache<K, V> {
     dir: PathBuf,
+    ttl: Option<Duration>,
+    format: Format,
+    compress: bool,
+    stats: Arc<CacheStats>,
     key: PhantomData<K>,
     value: PhantomData<V>,
 }
 
+// A clone is another handle to the same directory.
+// Deriving `Clone` would needlessly require `K: Clone` and `V: Clone`.
+impl<K, V> Clone for FileCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            dir: self.dir.clone(),
+            ttl: self.ttl,
+            format: self.format,
+            compress: self.compress,
+            stats: self.stats.clone(),
+            key: PhantomData,
+            value: PhantomData,
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for FileCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileCache")
+            .field("dir", &self.dir)
+            .field("ttl", &self.ttl)
+            .field("format", &self.format)
+            .field("compress", &self.compress)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
 impl<K: Hash, V> FileCache<K, V> {
-    pub async fn new(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let dir = std::env::var("XDG_CACHE_HOME")
-            .map_or(
-                Path::new(std::env::var("HOME")?.as_str()).join(".cache"),
-                PathBuf::from,
-            )
-            .join("webgrep")
-            .join(name);
-        tokio::fs::create_dir_all(&dir).await?;
+    pub async fn new(name: &str) -> Result<Self, Error> {
+        let dir = cache_dir(name)?;
+        task::block_in_place(|| open_dir(&dir))?;
         Ok(Self {
             dir,
+            ttl: None,
+            format: Format::default(),
+            compress: false,
+            stats: Arc::new(CacheStats::default()),
             key: PhantomData,
             value: PhantomData,
         })
     }
 
-    fn key_path(&self, k: &K) -> PathBuf {
-        self.dir.join(self.hash(k).to_string().as_str())
+    /// Ignore entries older than `ttl`.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    /// Read and write entries in `format`.
+    pub fn with_format(self, format: Format) -> Self {
+        Self { format, ..self }
     }
 
-    fn hash(&self, k: &K) -> u64 {
-        let mut h = DefaultHasher::new();
-        k.hash(&mut h);
-        h.finish()
+    /// Compress written entries with zstd.
+    /// Entries are read whether or not they are compressed.
+    pub fn with_compression(self, compress: bool) -> Self {
+        Self { compress, ..self }
+    }
+
+    fn key_path(&self, k: &K) -> PathBuf {
+        self.dir.join(hash(k).to_string().as_str())
     }
 }
 
-impl<K: Hash, V: serde::ser::Serialize + serde::de::DeserializeOwned> Cache<K, V>
-    for FileCache<K, V>
+// Each entry is stored with its key,
+// so entries can be listed.
+impl<
+        K: Hash + serde::ser::Serialize + serde::de::DeserializeOwned,
+        V: serde::ser::Serialize + serde::de::DeserializeOwned,
+    > Cache<K, V> for FileCache<K, V>
 {
     fn get(&self, k: &K) -> Option<V> {
-        // `bincode::deserialize_from` may panic
-        // if file contents don't match expected format.
-        task::block_in_place(|| std::fs::read(self.key_path(k)))
-            .ok()
-            .and_then(|x| bincode::deserialize(&x).ok())
+        // An entry that fails to decode
+        // counts as an error,
+        // and is fetched again.
+        let path = self.key_path(k);
+        match task::block_in_place(|| {
+            self.ttl
+                .is_none_or(|ttl| is_fresh(&path, ttl))
+                .then(|| std::fs::read(&path).ok())
+                .flatten()
+        }) {
+            Some(x) => match self.format.decode::<(K, V)>(&x) {
+                Ok((_, v)) => {
+                    self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                    Some(v)
+                }
+                Err(_) => {
+                    self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            },
+            None => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
     }
 
-    fn set(&self, k: &K, v: &V) -> Result<(), Box<dyn std::error::Error>> {
+    fn set(&self, k: &K, v: &V) -> Result<(), Error> {
         let path = self.key_path(k);
+        self.format
+            .encode(&(k, v), self.compress)
+            .and_then(|x| task::block_in_place(|| std::fs::write(path, x).map_err(Error::from)))
+            .inspect_err(|_| {
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+            })
+    }
+
+    fn entries(&self) -> Result<Vec<(K, V)>, Error> {
+        task::block_in_place(|| {
+            let mut entries = Vec::new();
+            for x in std::fs::read_dir(&self.dir)? {
+                let path = x?.path();
+                if self.ttl.is_some_and(|ttl| !is_fresh(&path, ttl)) {
+                    continue;
+                }
+                match std::fs::read(&path)
+                    .ok()
+                    .and_then(|x| self.format.decode(&x).ok())
+                {
+                    Some(entry) => entries.push(entry),
+                    None => {
+                        self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            Ok(entries)
+        })
+    }
+
+    fn stats(&self) -> Option<&CacheStats> {
+        Some(&self.stats)
+    }
+}
+
+/// A cache in one embedded database,
+/// instead of a file per entry.
+pub struct SledCache<K, V> {
+    db: sled::Db,
+    ttl: Option<Duration>,
+    format: Format,
+    compress: bool,
+    stats: Arc<CacheStats>,
+    key: PhantomData<K>,
+    value: PhantomData<V>,
+}
+
+// A clone is another handle to the same database.
+impl<K, V> Clone for SledCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            ttl: self.ttl,
+            format: self.format,
+            compress: self.compress,
+            stats: self.stats.clone(),
+            key: PhantomData,
+            value: PhantomData,
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for SledCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SledCache")
+            .field("ttl", &self.ttl)
+            .field("format", &self.format)
+            .field("compress", &self.compress)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Hash, V> SledCache<K, V> {
+    pub fn new(name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            db: task::block_in_place(|| {
+                let dir = cache_dir(name)?;
+                open_dir(&dir)?;
+                sled::open(dir).map_err(Error::from)
+            })?,
+            ttl: None,
+            format: Format::default(),
+            compress: false,
+            stats: Arc::new(CacheStats::default()),
+            key: PhantomData,
+            value: PhantomData,
+        })
+    }
+
+    /// Ignore entries older than `ttl`.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+
+    /// Read and write entries in `format`.
+    pub fn with_format(self, format: Format) -> Self {
+        Self { format, ..self }
+    }
+
+    /// Compress written entries with zstd.
+    /// Entries are read whether or not they are compressed.
+    pub fn with_compression(self, compress: bool) -> Self {
+        Self { compress, ..self }
+    }
+
+    fn is_fresh(&self, written: SystemTime) -> bool {
+        self.ttl
+            .is_none_or(|ttl| written.elapsed().is_ok_and(|age| age < ttl))
+    }
+}
+
+// Sled doesn't track when entries were written,
+// so each entry is stored with its time,
+// and with its key,
+// so entries can be listed.
+impl<
+        K: Hash + serde::ser::Serialize + serde::de::DeserializeOwned,
+        V: serde::ser::Serialize + serde::de::DeserializeOwned,
+    > Cache<K, V> for SledCache<K, V>
+{
+    fn get(&self, k: &K) -> Option<V> {
+        match task::block_in_place(|| self.db.get(hash(k).to_be_bytes())) {
+            Ok(Some(x)) => match self.format.decode::<(SystemTime, K, V)>(&x) {
+                Ok((written, _, v)) => {
+                    if self.is_fresh(written) {
+                        self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                        Some(v)
+                    } else {
+                        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                        None
+                    }
+                }
+                Err(_) => {
+                    self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+            },
+            Ok(None) => {
+                self.stats.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(_) => {
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    fn set(&self, k: &K, v: &V) -> Result<(), Error> {
+        self.format
+            .encode(&(SystemTime::now(), k, v), self.compress)
+            .and_then(|x| {
+                task::block_in_place(|| self.db.insert(hash(k).to_be_bytes(), x))
+                    .map_err(Error::from)
+            })
+            .map(|_| ())
+            .inspect_err(|_| {
+                self.stats.errors.fetch_add(1, Ordering::Relaxed);
+            })
+    }
+
+    fn entries(&self) -> Result<Vec<(K, V)>, Error> {
         task::block_in_place(|| {
-            bincode::serialize_into(std::io::BufWriter::new(std::fs::File::create(path)?), v)
+            let mut entries = Vec::new();
+            for x in self.db.iter() {
+                let (_, x) = x?;
+                match self.format.decode::<(SystemTime, K, V)>(&x) {
+                    Ok((written, k, v)) => {
+                        if self.is_fresh(written) {
+                            entries.push((k, v));
+                        }
+                    }
+                    Err(_) => {
+                        self.stats.errors.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            Ok(entries)
         })
-        .map_err(|e| e.into())
+    }
+
+    fn stats(&self) -> Option<&CacheStats> {
+        Some(&self.stats)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CacheStats {
+    pub hits: AtomicU64,
+    /// Lookups of missing or stale entries
+    pub misses: AtomicU64,
+    /// Entries that failed to read or write
+    pub errors: AtomicU64,
+}
+
+/// A cache in memory,
+/// for runs that shouldn't persist pages.
+pub struct MemoryCache<K, V> {
+    map: Arc<DashMap<K, (Instant, V)>>,
+    ttl: Option<Duration>,
+    stats: Arc<CacheStats>,
+}
+
+// A clone is another handle to the same map.
+impl<K, V> Clone for MemoryCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            map: self.map.clone(),
+            ttl: self.ttl,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for MemoryCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryCache")
+            .field("ttl", &self.ttl)
+            .field("stats", &self.stats)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K: Eq + Hash, V> MemoryCache<K, V> {
+    pub fn new() -> Self {
+        Self {
+            map: Arc::new(DashMap::new()),
+            ttl: None,
+            stats: Arc::new(CacheStats::default()),
+        }
+    }
+
+    /// Ignore entries older than `ttl`.
+    pub fn with_ttl(self, ttl: Duration) -> Self {
+        Self {
+            ttl: Some(ttl),
+            ..self
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Default for MemoryCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Cache<K, V> for MemoryCache<K, V> {
+    fn get(&self, k: &K) -> Option<V> {
+        let v = self
+            .map
+            .get(k)
+            .filter(|x| self.ttl.is_none_or(|ttl| x.0.elapsed() < ttl))
+            .map(|x| x.1.clone());
+        match v {
+            Some(_) => self.stats.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.stats.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        v
+    }
+
+    fn set(&self, k: &K, v: &V) -> Result<(), Error> {
+        self.map.insert(k.clone(), (Instant::now(), v.clone()));
+        Ok(())
+    }
+
+    fn entries(&self) -> Result<Vec<(K, V)>, Error> {
+        Ok(self
+            .map
+            .iter()
+            .filter(|x| self.ttl.is_none_or(|ttl| x.0.elapsed() < ttl))
+            .map(|x| (x.key().clone(), x.1.clone()))
+            .collect())
+    }
+
+    fn stats(&self) -> Option<&CacheStats> {
+        Some(&self.stats)
+    }
+}
+
+/// Write `entries` to a gzipped tar archive,
+/// with a file per entry,
+/// named by its key in URL-safe base64,
+/// containing its value as JSON.
+pub fn export<K: std::fmt::Display, V: serde::ser::Serialize>(
+    w: impl Write,
+    entries: impl IntoIterator<Item = (K, V)>,
+) -> Result<(), Error> {
+    let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+        w,
+        flate2::Compression::default(),
+    ));
+    for (k, v) in entries {
+        let data = serde_json::to_vec(&v)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        archive.append_data(
+            &mut header,
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(k.to_string()),
+            data.as_slice(),
+        )?;
+    }
+    archive.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+/// Read entries from an archive written by `export`.
+pub fn import<K: std::str::FromStr, V: serde::de::DeserializeOwned>(
+    r: impl Read,
+) -> Result<Vec<(K, V)>, Error> {
+    let mut entries = Vec::new();
+    for x in tar::Archive::new(flate2::read::GzDecoder::new(r)).entries()? {
+        let mut x = x?;
+        let name = x.path()?.to_string_lossy().into_owned();
+        let k = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(&name)
+            .ok()
+            .and_then(|x| String::from_utf8(x).ok())
+            .and_then(|x| x.parse().ok())
+            .ok_or(Error::EntryName(name))?;
+        let mut data = Vec::new();
+        x.read_to_end(&mut data)?;
+        entries.push((k, serde_json::from_slice(&data)?));
+    }
+    Ok(entries)
+}
+
+/// Version of the layout of cached entries.
+/// Bump it when a cached type changes,
+/// like `client::Response`,
+/// so old caches are cleared
+/// instead of failing entry by entry.
+const FORMAT_VERSION: u32 = 2;
+
+/// Create `dir`,
+/// clearing it first
+/// if it was written with another `FORMAT_VERSION`,
+/// or before caches had versions.
+/// The version is kept beside `dir`,
+/// so it isn't read as an entry.
+fn open_dir(dir: &Path) -> Result<(), Error> {
+    let mut version_path = dir.as_os_str().to_owned();
+    version_path.push(".version");
+    let version = std::fs::read_to_string(&version_path)
+        .ok()
+        .and_then(|x| x.trim().parse::<u32>().ok());
+    if version != Some(FORMAT_VERSION) {
+        match std::fs::remove_dir_all(dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(&version_path, FORMAT_VERSION.to_string())?;
+    } else {
+        std::fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+/// `$XDG_CACHE_HOME/webgrep/name`,
+/// default `~/.cache/webgrep/name`.
+fn cache_dir(name: &str) -> Result<PathBuf, Error> {
+    Ok(std::env::var("XDG_CACHE_HOME")
+        .map_or(
+            Path::new(std::env::var("HOME")?.as_str()).join(".cache"),
+            PathBuf::from,
+        )
+        .join("webgrep")
+        .join(name))
+}
+
+fn hash(k: &impl Hash) -> u64 {
+    let mut h = DefaultHasher::new();
+    k.hash(&mut h);
+    h.finish()
+}
+
+fn is_fresh(path: &Path, ttl: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|x| x.modified())
+        .ok()
+        .and_then(|x| x.elapsed().ok())
+        .is_some_and(|age| age < ttl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_dir_clears_caches_from_other_versions() {
+        let dir = std::env::temp_dir().join(format!("webgrep-open-dir-{}", std::process::id()));
+        let version_path = dir.with_extension("version");
+        let _ = std::fs::remove_dir_all(&dir);
+        let _ = std::fs::remove_file(&version_path);
+
+        // A cache from before versions
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("entry"), b"old").unwrap();
+        open_dir(&dir).unwrap();
+        assert!(!dir.join("entry").exists());
+        assert_eq!(
+            std::fs::read_to_string(&version_path).unwrap(),
+            FORMAT_VERSION.to_string()
+        );
+
+        // A cache from this version
+        std::fs::write(dir.join("entry"), b"new").unwrap();
+        open_dir(&dir).unwrap();
+        assert!(dir.join("entry").exists());
+
+        // A cache from another version
+        std::fs::write(&version_path, (FORMAT_VERSION - 1).to_string()).unwrap();
+        open_dir(&dir).unwrap();
+        assert!(!dir.join("entry").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_file(&version_path).unwrap();
     }
 }
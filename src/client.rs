@@ -1,34 +1,94 @@
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
 
-const BODY_SIZE_LIMIT: u64 = 104857600; // bytes
+pub const BODY_SIZE_LIMIT: u64 = 104857600; // bytes
 
-pub type Response = Result<Body, Error>;
+pub type Response = Result<Fetched, Error>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// A body,
+/// with the HTTP status it came with
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fetched {
+    pub status: u16,
+    pub body: Body,
+}
+
+impl Fetched {
+    /// Whether `status` is a client or server error,
+    /// like 404
+    pub fn is_error(&self) -> bool {
+        self.status >= 400
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Body {
     Html(String),
-    Pdf(String),
+    // PDFs are binary,
+    // so they can't be decoded as text
+    // until parsed.
+    Pdf(Vec<u8>),
     Plain(String),
+    Css(String),
+    Script(String),
+    Xml(String),
+    Json(String),
+    Markdown(String),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+// Errors are cached with pages,
+// so they must be serializable,
+// unlike `reqwest::Error`,
+// and new variants go at the end,
+// to keep the layout of old ones.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
 pub enum Error {
+    #[error("unsupported content type `{0}`")]
     InvalidContentType(String),
+    // The size from `content-length`,
+    // if the server sent it.
+    #[error("body is longer than the size limit")]
     ContentLengthTooLong(Option<u64>),
+    #[error(transparent)]
     Other(ReqwestError),
+    #[error("skipped content type `{0}`")]
+    SkippedContentType(String),
+}
+
+impl Error {
+    /// Whether this error depends on client options,
+    /// like skipped media types,
+    /// instead of only on the page,
+    /// so it can't be cached.
+    pub fn is_from_options(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidContentType(_)
+                | Error::SkippedContentType(_)
+                | Error::ContentLengthTooLong(_)
+        )
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
 pub enum ReqwestError {
+    #[error("failed to build request")]
     Builder,
+    #[error("failed to follow redirect")]
     Redirect,
+    #[error("HTTP status {0}")]
     Status(u16),
+    #[error("request timed out")]
     Timeout,
+    #[error("failed to send request")]
     Request,
+    #[error("failed to connect")]
     Connect,
+    #[error("failed to read body")]
     Body,
+    #[error("failed to decode body")]
     Decode,
+    #[error("{0}")]
     Other(String),
 }
 
@@ -59,41 +119,224 @@ impl From<reqwest::Error> for ReqwestError {
 #[async_trait::async_trait]
 pub trait Client {
     async fn get(&self, url: &Url) -> Response;
+
+    /// Status of a `HEAD` request for `url`
+    async fn head(&self, url: &Url) -> Result<u16, Error>;
+
+    /// Post `body` as JSON to `url`,
+    /// with `authorization` as the `Authorization` header,
+    /// if given.
+    async fn post_json(
+        &self,
+        url: &Url,
+        authorization: Option<&str>,
+        body: String,
+    ) -> Result<(), Error>;
 }
 
 #[async_trait::async_trait]
 impl Client for reqwest::Client {
     async fn get(&self, url: &Url) -> Response {
-        match self.get(url.as_ref()).send().await {
-            Ok(r) => {
-                // The default `content-type` is `application/octet-stream`,
-                // <https://www.w3.org/Protocols/rfc2616/rfc2616-sec7.html#sec7.2.1>.
-                let content_type = r
-                    .headers()
-                    .get("content-type")
-                    .map_or("application/octet-stream", |x| x.to_str().unwrap_or(""));
-                if content_type.contains("text/html") {
-                    read_body(r).await.map(Body::Html)
-                } else if content_type.contains("application/pdf") {
-                    read_body(r).await.map(Body::Pdf)
-                } else if content_type.contains("text/plain") {
-                    read_body(r).await.map(Body::Plain)
-                } else {
-                    Err(Error::InvalidContentType(content_type.to_owned()))
-                }
-            }
-            Err(e) => Err(Error::Other(e.into())),
+        get(self, url, &[], &[], BODY_SIZE_LIMIT).await
+    }
+
+    async fn head(&self, url: &Url) -> Result<u16, Error> {
+        self.head(url.as_ref())
+            .send()
+            .await
+            .map(|r| r.status().as_u16())
+            .map_err(|e| Error::Other(e.into()))
+    }
+
+    async fn post_json(
+        &self,
+        url: &Url,
+        authorization: Option<&str>,
+        body: String,
+    ) -> Result<(), Error> {
+        let mut request = self
+            .post(url.as_ref())
+            .header("content-type", "application/json")
+            .body(body);
+        if let Some(x) = authorization {
+            request = request.header("authorization", x);
         }
+        request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map(|_| ())
+            .map_err(|e| Error::Other(e.into()))
     }
 }
 
-async fn read_body(r: reqwest::Response) -> Result<String, Error> {
-    if r.content_length().map_or(true, |x| x < BODY_SIZE_LIMIT) {
-        // TODO: incrementally read with `chunk`,
-        // short circuit if bytes gets too long,
-        // and decode with source from `text_with_charset`.
-        r.text().await.map_err(|e| Error::Other(e.into()))
-    } else {
-        Err(Error::ContentLengthTooLong(r.content_length()))
+/// A `reqwest::Client`
+/// with media types to read differently
+/// and a limit on body size
+pub struct HttpClient {
+    client: reqwest::Client,
+    plain_types: Vec<String>,
+    skip_types: Vec<String>,
+    max_body_size: u64,
+}
+
+impl HttpClient {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self {
+            client,
+            plain_types: Vec::new(),
+            skip_types: Vec::new(),
+            max_body_size: BODY_SIZE_LIMIT,
+        }
+    }
+
+    /// Search bodies of these media types,
+    /// like `text/csv`,
+    /// as plain text
+    pub fn with_plain_types(self, plain_types: Vec<String>) -> Self {
+        Self {
+            plain_types,
+            ..self
+        }
+    }
+
+    /// Skip bodies of these media types
+    /// without reading them
+    pub fn with_skip_types(self, skip_types: Vec<String>) -> Self {
+        Self { skip_types, ..self }
     }
+
+    /// Fail bodies longer than `max_body_size` bytes
+    /// without reading the rest.
+    /// A body of exactly `max_body_size` bytes is read.
+    pub fn with_max_body_size(self, max_body_size: u64) -> Self {
+        Self {
+            max_body_size,
+            ..self
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Client for HttpClient {
+    async fn get(&self, url: &Url) -> Response {
+        get(
+            &self.client,
+            url,
+            &self.plain_types,
+            &self.skip_types,
+            self.max_body_size,
+        )
+        .await
+    }
+
+    async fn head(&self, url: &Url) -> Result<u16, Error> {
+        Client::head(&self.client, url).await
+    }
+
+    async fn post_json(
+        &self,
+        url: &Url,
+        authorization: Option<&str>,
+        body: String,
+    ) -> Result<(), Error> {
+        Client::post_json(&self.client, url, authorization, body).await
+    }
+}
+
+async fn get(
+    client: &reqwest::Client,
+    url: &Url,
+    plain_types: &[String],
+    skip_types: &[String],
+    max_body_size: u64,
+) -> Response {
+    match client.get(url.as_ref()).send().await {
+        // Error pages have bodies worth searching too.
+        Ok(r) => {
+            let status = r.status().as_u16();
+            // The default `content-type` is `application/octet-stream`,
+            // <https://www.w3.org/Protocols/rfc2616/rfc2616-sec7.html#sec7.2.1>.
+            let content_type = r
+                .headers()
+                .get("content-type")
+                .map_or("application/octet-stream", |x| x.to_str().unwrap_or(""));
+            // Parameters,
+            // like `charset`,
+            // don't change what a body is.
+            let media_type = content_type.split(';').next().unwrap_or("").trim();
+            let is_any =
+                |types: &[String]| types.iter().any(|x| x.eq_ignore_ascii_case(media_type));
+            let body = if is_any(skip_types) {
+                Err(Error::SkippedContentType(content_type.to_owned()))
+            } else if is_any(plain_types) {
+                read_body(r, max_body_size).await.map(Body::Plain)
+            } else if content_type.contains("text/html") {
+                read_body(r, max_body_size).await.map(Body::Html)
+            } else if content_type.contains("application/pdf") {
+                read_bytes(r, max_body_size).await.map(Body::Pdf)
+            } else if content_type.contains("text/markdown")
+                || (content_type.contains("text/plain") && url.path().ends_with(".md"))
+            {
+                read_body(r, max_body_size).await.map(Body::Markdown)
+            } else if content_type.contains("text/plain") {
+                read_body(r, max_body_size).await.map(Body::Plain)
+            } else if content_type.contains("text/css") {
+                read_body(r, max_body_size).await.map(Body::Css)
+            } else if content_type.contains("javascript") || content_type.contains("ecmascript") {
+                read_body(r, max_body_size).await.map(Body::Script)
+            } else if content_type.contains("application/xml") || content_type.contains("text/xml")
+            {
+                read_body(r, max_body_size).await.map(Body::Xml)
+            } else if content_type.contains("application/json") {
+                read_body(r, max_body_size).await.map(Body::Json)
+            } else {
+                Err(Error::InvalidContentType(content_type.to_owned()))
+            };
+            body.map(|body| Fetched { status, body })
+        }
+        Err(e) => Err(Error::Other(e.into())),
+    }
+}
+
+async fn read_body(r: reqwest::Response, max_body_size: u64) -> Result<String, Error> {
+    let content_type = r
+        .headers()
+        .get("content-type")
+        .and_then(|x| x.to_str().ok())
+        .unwrap_or("")
+        .to_owned();
+    Ok(decode(&content_type, &read_bytes(r, max_body_size).await?))
+}
+
+async fn read_bytes(mut r: reqwest::Response, max_body_size: u64) -> Result<Vec<u8>, Error> {
+    let content_length = r.content_length();
+    if content_length.is_some_and(|x| x > max_body_size) {
+        return Err(Error::ContentLengthTooLong(content_length));
+    }
+    // Servers can omit `content-length`,
+    // or send more than it says,
+    // so count bytes as they arrive.
+    let mut bytes = Vec::new();
+    while let Some(chunk) = r.chunk().await.map_err(|e| Error::Other(e.into()))? {
+        if (bytes.len() + chunk.len()) as u64 > max_body_size {
+            return Err(Error::ContentLengthTooLong(content_length));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Decode `bytes` with the charset in `content_type`,
+/// or UTF-8,
+/// like `reqwest::Response::text`
+fn decode(content_type: &str, bytes: &[u8]) -> String {
+    let encoding = content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|x| x.split_once('='))
+        .find(|(k, _)| k.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, v)| encoding_rs::Encoding::for_label(v.trim().trim_matches('"').as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+    encoding.decode(bytes).0.into_owned()
 }
@@ -0,0 +1,136 @@
+use crate::cache::{Cache, MemoryCache};
+use crate::client::{Client, HttpClient, Response};
+pub use crate::run::Config;
+use crate::run::{run_with_matches, CrawledPage, Error, Snippet};
+use futures::{FutureExt, Stream, StreamExt};
+use reqwest::Url;
+use std::sync::Arc;
+
+/// A page matching `Config::search_re`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Match {
+    /// URL of the matching page
+    pub url: Url,
+    /// URLs from a starting URL to the matching page
+    pub path: Vec<Url>,
+    /// First match,
+    /// unless `Config::invert_match`
+    pub snippet: Option<Snippet>,
+}
+
+impl From<&CrawledPage> for Match {
+    fn from(page: &CrawledPage) -> Self {
+        Self {
+            // Every path ends with its page.
+            url: page.path.last().unwrap().clone(),
+            path: page.path.clone(),
+            snippet: page.snippet.clone(),
+        }
+    }
+}
+
+/// A cache,
+/// client,
+/// and config
+/// to crawl with
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use regex::Regex;
+/// use reqwest::Url;
+/// use webgrep::{Config, Crawler};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let crawler = Crawler::with_config(Config {
+///         max_depth: Some(1),
+///         ..Config::new(Regex::new("foo").unwrap())
+///     });
+///     let mut matches = Box::pin(crawler.crawl(vec![Url::parse("https://example.com").unwrap()]));
+///     while let Some(m) = matches.next().await {
+///         println!("{}", m.unwrap().url);
+///     }
+/// }
+/// ```
+pub struct Crawler<C, L: 'static> {
+    cache: Arc<C>,
+    client: &'static L,
+    config: &'static Config,
+}
+
+impl<C: Cache<Url, Response> + Send + Sync + 'static, L: Client + Sync> Crawler<C, L> {
+    pub fn new(cache: Arc<C>, client: &'static L, config: &'static Config) -> Self {
+        Self {
+            cache,
+            client,
+            config,
+        }
+    }
+
+    /// Like `run`,
+    /// but yield each match,
+    /// instead of writing it,
+    /// and end with an error if `run` fails.
+    /// This must be polled on a multi-threaded runtime.
+    pub fn crawl(&self, urls: Vec<Url>) -> impl Stream<Item = Result<Match, Error>> {
+        let (sender, receiver) = futures::channel::mpsc::unbounded();
+        // `run_with_matches` drops the sender when it finishes,
+        // ending both streams.
+        let done = run_with_matches(
+            std::io::sink(),
+            indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+            self.cache.clone(),
+            self.client,
+            self.config,
+            urls,
+            move |page| {
+                // If the receiver is gone,
+                // nobody wants the match.
+                _ = sender.unbounded_send(Match::from(page));
+            },
+        )
+        .map(|res| res.err().map(Err))
+        .into_stream()
+        .filter_map(futures::future::ready);
+        futures::stream::select(receiver.map(Ok), done)
+    }
+}
+
+impl Crawler<MemoryCache<Url, Response>, HttpClient> {
+    /// A crawler with a default HTTP client,
+    /// caching pages in memory.
+    /// The client and `config` are leaked,
+    /// because every request task borrows them,
+    /// so keep one `Crawler`
+    /// instead of making one per crawl.
+    pub fn with_config(config: Config) -> Self {
+        Self::new(
+            Arc::new(MemoryCache::new()),
+            Box::leak(Box::new(HttpClient::new(reqwest::Client::new()))),
+            Box::leak(Box::new(config)),
+        )
+    }
+}
+
+/// Like `Crawler::crawl`,
+/// with `Crawler::with_config`.
+///
+/// ```no_run
+/// use futures::StreamExt;
+/// use regex::Regex;
+/// use reqwest::Url;
+/// use webgrep::Config;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let matches: Vec<_> = webgrep::crawl(
+///         Config::new(Regex::new("foo").unwrap()),
+///         vec![Url::parse("https://example.com").unwrap()],
+///     )
+///     .collect()
+///     .await;
+/// }
+/// ```
+pub fn crawl(config: Config, urls: Vec<Url>) -> impl Stream<Item = Result<Match, Error>> {
+    Crawler::with_config(config).crawl(urls)
+}
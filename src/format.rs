@@ -0,0 +1,54 @@
+use crate::pattern::PatternEngine;
+use regex::{Captures, Regex};
+use reqwest::Url;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+pub const DIM: &str = "\x1b[2m";
+const HIGHLIGHT: &str = "\x1b[1;4m";
+pub const MATCH: &str = "\x1b[1;31m";
+pub const RESET: &str = "\x1b[0m";
+
+/// Make matches of `re` in `text`
+/// bold and underlined.
+pub fn highlight(text: &str, re: &PatternEngine) -> String {
+    let mut s = String::with_capacity(text.len());
+    let mut end = 0;
+    for m in re.find_iter(text).filter(|m| !m.as_str().is_empty()) {
+        s.push_str(&text[end..m.start()]);
+        s.push_str(HIGHLIGHT);
+        s.push_str(m.as_str());
+        s.push_str(RESET);
+        end = m.end();
+    }
+    s.push_str(&text[end..]);
+    s
+}
+
+pub struct Fields<'a> {
+    pub url: &'a Url,
+    pub path: &'a str,
+    pub depth: u64,
+    pub matched: &'a str,
+    pub latency: Option<Duration>,
+}
+
+/// Replace `{url}`, `{path}`, `{depth}`, `{match}`, and `{latency_ms}`
+/// in `template`.
+/// `{latency_ms}` is empty
+/// for pages from the cache.
+pub fn render(template: &str, fields: &Fields) -> String {
+    static TOKEN_RE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(\w+)\}").unwrap());
+    TOKEN_RE
+        .replace_all(template, |caps: &Captures| match &caps[1] {
+            "url" => fields.url.to_string(),
+            "path" => fields.path.to_owned(),
+            "depth" => fields.depth.to_string(),
+            "match" => fields.matched.to_owned(),
+            "latency_ms" => fields
+                .latency
+                .map_or_else(String::new, |x| x.as_millis().to_string()),
+            _ => caps[0].to_owned(),
+        })
+        .into_owned()
+}
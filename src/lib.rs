@@ -1,6 +1,19 @@
 pub mod cache;
 pub mod client;
+pub mod crawler;
+mod format;
 mod node;
+pub mod pattern;
+pub mod report;
 mod run;
 
-pub use crate::run::run;
+pub use crate::cache::{FileCache, MemoryCache, SledCache};
+pub use crate::client::HttpClient;
+pub use crate::crawler::{crawl, Crawler, Match};
+pub use crate::node::Node;
+#[doc(hidden)]
+pub use crate::run::{inner_text, links};
+pub use crate::run::{
+    run, Config, CrawledPage, DeadLink, Error, ErrorFormat, NodePriority, Page, Snippet, Summary,
+    Webhook,
+};
@@ -18,40 +18,59 @@ impl<T> Node<T> {
         }
     }
 
+    /// A root node at `depth`,
+    /// instead of 0.
+    pub fn with_depth(depth: u64, value: T) -> Self {
+        Node {
+            depth,
+            parent: None,
+            value,
+        }
+    }
+
+    /// This node,
+    /// with its value changed by `f`.
+    pub fn map_value(self, f: impl FnOnce(T) -> T) -> Self {
+        Node {
+            value: f(self.value),
+            ..self
+        }
+    }
+
     pub fn depth(&self) -> u64 {
         self.depth
     }
 
+    pub fn parent(&self) -> Option<&Node<T>> {
+        self.parent.as_deref()
+    }
+
     pub fn value(&self) -> &T {
         &self.value
     }
 
+    /// Values from this node to the root
+    pub fn ancestors(&self) -> Ancestors<'_, T> {
+        Ancestors { node: Some(self) }
+    }
+
     pub fn path_from_root(&self) -> Vec<&T> {
-        match &self.parent {
-            Some(p) => {
-                let mut xs = p.path_from_root();
-                xs.push(&self.value);
-                xs
-            }
-            None => Vec::from([&self.value]),
-        }
+        let mut xs: Vec<_> = self.ancestors().collect();
+        xs.reverse();
+        xs
     }
 }
 
-pub struct NodePathIterator<'a, T> {
-    node: Option<&'a Arc<Node<T>>>,
+pub struct Ancestors<'a, T> {
+    node: Option<&'a Node<T>>,
 }
 
-impl<'a, T> Iterator for NodePathIterator<'a, T> {
+impl<'a, T> Iterator for Ancestors<'a, T> {
     type Item = &'a T;
 
     fn next(&mut self) -> Option<Self::Item> {
         let x = self.node?;
-        self.node = x.parent.as_ref();
+        self.node = x.parent();
         Some(&x.value)
     }
 }
-
-pub fn path_to_root<T>(x: &Arc<Node<T>>) -> NodePathIterator<T> {
-    NodePathIterator { node: Some(x) }
-}
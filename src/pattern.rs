@@ -0,0 +1,253 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A search pattern,
+/// compiled by `regex`,
+/// or,
+/// with the `fancy-regex` feature,
+/// by `fancy-regex`
+/// if it needs lookaround or backreferences
+#[derive(Clone, Debug)]
+pub enum PatternEngine {
+    Fast(regex::Regex),
+    #[cfg(feature = "fancy-regex")]
+    Fancy(fancy_regex::Regex),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+    #[cfg(feature = "fancy-regex")]
+    #[error(transparent)]
+    FancyRegex(Box<fancy_regex::Error>),
+}
+
+/// A match of a `PatternEngine` in `text`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match<'t> {
+    text: &'t str,
+    start: usize,
+    end: usize,
+}
+
+impl<'t> Match<'t> {
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn as_str(&self) -> &'t str {
+        &self.text[self.start..self.end]
+    }
+}
+
+impl PatternEngine {
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        Self::with_flags(pattern, false, false)
+    }
+
+    /// Compile `pattern`,
+    /// matching case insensitively
+    /// and letting `.` match newlines
+    /// if asked
+    pub fn with_flags(
+        pattern: &str,
+        ignore_case: bool,
+        dot_matches_new_line: bool,
+    ) -> Result<Self, Error> {
+        let re = regex::RegexBuilder::new(pattern)
+            .case_insensitive(ignore_case)
+            .dot_matches_new_line(dot_matches_new_line)
+            .build();
+        #[cfg(feature = "fancy-regex")]
+        if re.is_err() {
+            // `fancy-regex` has no builder for these,
+            // but inline flags work the same.
+            let flags = match (ignore_case, dot_matches_new_line) {
+                (false, false) => "",
+                (true, false) => "(?i)",
+                (false, true) => "(?s)",
+                (true, true) => "(?is)",
+            };
+            return fancy_regex::Regex::new(&format!("{}{}", flags, pattern))
+                .map(PatternEngine::Fancy)
+                .map_err(|e| Error::FancyRegex(Box::new(e)));
+        }
+        Ok(PatternEngine::Fast(re?))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            PatternEngine::Fast(re) => re.as_str(),
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => re.as_str(),
+        }
+    }
+
+    /// Whether the pattern has a capture group named `name`
+    pub fn has_group(&self, name: &str) -> bool {
+        match self {
+            PatternEngine::Fast(re) => re.capture_names().flatten().any(|x| x == name),
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => re.capture_names().flatten().any(|x| x == name),
+        }
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        match self {
+            PatternEngine::Fast(re) => re.is_match(text),
+            // `fancy-regex` can fail at runtime,
+            // like when it backtracks too much.
+            // We treat that as no match.
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => re.is_match(text).unwrap_or(false),
+        }
+    }
+
+    pub fn find<'t>(&self, text: &'t str) -> Option<Match<'t>> {
+        match self {
+            PatternEngine::Fast(re) => re.find(text).map(|m| Match {
+                text,
+                start: m.start(),
+                end: m.end(),
+            }),
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => re.find(text).ok().flatten().map(|m| Match {
+                text,
+                start: m.start(),
+                end: m.end(),
+            }),
+        }
+    }
+
+    pub fn find_iter<'a, 't: 'a>(
+        &'a self,
+        text: &'t str,
+    ) -> Box<dyn Iterator<Item = Match<'t>> + 'a> {
+        match self {
+            PatternEngine::Fast(re) => Box::new(re.find_iter(text).map(move |m| Match {
+                text,
+                start: m.start(),
+                end: m.end(),
+            })),
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => Box::new(re.find_iter(text).map_while(Result::ok).map(
+                move |m| Match {
+                    text,
+                    start: m.start(),
+                    end: m.end(),
+                },
+            )),
+        }
+    }
+
+    /// Expand `template`,
+    /// like `$1`,
+    /// for each match in `text`
+    pub fn expand_iter<'a>(
+        &'a self,
+        template: &'a str,
+        text: &'a str,
+    ) -> Box<dyn Iterator<Item = String> + 'a> {
+        match self {
+            PatternEngine::Fast(re) => Box::new(re.captures_iter(text).map(move |caps| {
+                let mut s = String::new();
+                caps.expand(template, &mut s);
+                s
+            })),
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => Box::new(re.captures_iter(text).map_while(Result::ok).map(
+                move |caps| {
+                    let mut s = String::new();
+                    caps.expand(template, &mut s);
+                    s
+                },
+            )),
+        }
+    }
+
+    /// Text captured by the group named `name`
+    /// for each match in `text`,
+    /// skipping matches where it didn't participate
+    pub fn group_iter<'a>(
+        &'a self,
+        name: &'a str,
+        text: &'a str,
+    ) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        match self {
+            PatternEngine::Fast(re) => Box::new(
+                re.captures_iter(text)
+                    .filter_map(move |caps| caps.name(name).map(|m| m.as_str())),
+            ),
+            #[cfg(feature = "fancy-regex")]
+            PatternEngine::Fancy(re) => Box::new(
+                re.captures_iter(text)
+                    .map_while(Result::ok)
+                    .filter_map(move |caps| caps.name(name).map(|m| m.as_str())),
+            ),
+        }
+    }
+}
+
+impl From<regex::Regex> for PatternEngine {
+    fn from(re: regex::Regex) -> Self {
+        PatternEngine::Fast(re)
+    }
+}
+
+impl FromStr for PatternEngine {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for PatternEngine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_patterns_use_regex() {
+        assert!(matches!(
+            PatternEngine::new("foo.*bar").unwrap(),
+            PatternEngine::Fast(_)
+        ));
+    }
+
+    #[cfg(not(feature = "fancy-regex"))]
+    #[test]
+    fn lookaround_needs_fancy_regex() {
+        assert!(PatternEngine::new("foo(?=bar)").is_err());
+    }
+
+    #[cfg(feature = "fancy-regex")]
+    #[test]
+    fn lookaround_uses_fancy_regex() {
+        let re = PatternEngine::with_flags("foo(?!bar).baz", true, true).unwrap();
+        assert!(matches!(re, PatternEngine::Fancy(_)));
+        assert_eq!(
+            re.find_iter("foobar baz FOO\nBAZ")
+                .map(|m| m.as_str())
+                .collect::<Vec<_>>(),
+            vec!["FOO\nBAZ"]
+        );
+        assert_eq!(
+            PatternEngine::new(r"(\w)\1")
+                .unwrap()
+                .expand_iter("<$1>", "abbcdd")
+                .collect::<Vec<_>>(),
+            vec!["<b>", "<d>"]
+        );
+    }
+}
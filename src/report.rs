@@ -0,0 +1,212 @@
+use crate::run::Summary;
+use itertools::Itertools;
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// The report should be one shareable file,
+// so everything it needs is inline.
+const HEAD: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>webgrep report</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+ul { list-style: none; }
+details { margin-left: 1.5em; }
+summary, .leaf { margin-left: 1.5em; }
+.match { font-weight: bold; color: #b00; }
+</style>
+</head>
+<body>
+<h1>webgrep report</h1>
+"#;
+
+const FOOT: &str = r#"</body>
+</html>
+"#;
+
+/// Write `summary` as a self-contained HTML report.
+/// `summary` should be from a run with `Config::record_crawl`.
+pub fn write_html(w: &mut impl Write, summary: &Summary) -> std::fmt::Result {
+    w.write_str(HEAD)?;
+    writeln!(
+        w,
+        "<p>{} pages searched, {} errors, {} matches</p>",
+        summary.pages, summary.errors, summary.matches
+    )?;
+
+    w.write_str("<h2>Matches</h2>\n<ul>\n")?;
+    for page in summary.crawl.iter().filter(|x| x.is_match) {
+        if let Some(url) = page.path.last() {
+            writeln!(
+                w,
+                r#"<li><a href="{}">{}</a></li>"#,
+                escape(url.as_str()),
+                escape(&display_path(&page.path)),
+            )?;
+        }
+    }
+    w.write_str("</ul>\n")?;
+
+    w.write_str("<h2>Crawl</h2>\n")?;
+    let mut tree = Tree::default();
+    for page in &summary.crawl {
+        tree.insert(&page.path, page.is_match);
+    }
+    for (url, child) in &tree.children {
+        child.write_html(w, url)?;
+    }
+
+    w.write_str(FOOT)
+}
+
+/// Write the pages in `summary` as an indented tree,
+/// a line per page,
+/// like `  URL [matched]`,
+/// indented two spaces per link from a starting URL.
+/// `summary` should be from a run with `Config::record_crawl`.
+pub fn write_tree(w: &mut impl Write, summary: &Summary) -> std::fmt::Result {
+    let mut tree = Tree::default();
+    for page in &summary.crawl {
+        tree.insert(&page.path, page.is_match);
+    }
+    for (url, child) in &tree.children {
+        child.write_text(w, url, 0)?;
+    }
+    Ok(())
+}
+
+// The same URL may be reached by many paths,
+// so the tree is keyed by path,
+// not URL.
+#[derive(Default)]
+struct Tree<'a> {
+    is_match: bool,
+    children: BTreeMap<&'a Url, Tree<'a>>,
+}
+
+impl<'a> Tree<'a> {
+    fn insert(&mut self, path: &'a [Url], is_match: bool) {
+        match path.split_first() {
+            Some((url, rest)) => self.children.entry(url).or_default().insert(rest, is_match),
+            None => self.is_match = is_match,
+        }
+    }
+
+    fn write_html(&self, w: &mut impl Write, url: &Url) -> std::fmt::Result {
+        let link = format!(
+            r#"<a href="{}"{}>{}</a>"#,
+            escape(url.as_str()),
+            if self.is_match {
+                r#" class="match""#
+            } else {
+                ""
+            },
+            escape(url.as_str()),
+        );
+        if self.children.is_empty() {
+            writeln!(w, r#"<div class="leaf">{}</div>"#, link)
+        } else {
+            writeln!(w, "<details open>\n<summary>{}</summary>", link)?;
+            for (url, child) in &self.children {
+                child.write_html(w, url)?;
+            }
+            w.write_str("</details>\n")
+        }
+    }
+
+    fn write_text(&self, w: &mut impl Write, url: &Url, depth: usize) -> std::fmt::Result {
+        writeln!(
+            w,
+            "{}{}{}",
+            "  ".repeat(depth),
+            url,
+            if self.is_match { " [matched]" } else { "" }
+        )?;
+        for (url, child) in &self.children {
+            child.write_text(w, url, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write one line per dead link in `summary`,
+/// like `URL > ... > URL [STATUS]`.
+/// `summary` should be from a run with `Config::record_dead_links`.
+pub fn write_dead_links(w: &mut impl Write, summary: &Summary) -> std::fmt::Result {
+    for link in &summary.dead_links {
+        writeln!(w, "{} [{}]", display_path(&link.path), link.status)?;
+    }
+    Ok(())
+}
+
+/// Write `summary` to a SQLite database at `path`.
+/// `summary` should be from a run with `Config::record_crawl`.
+pub fn write_sqlite(path: &Path, summary: &Summary) -> rusqlite::Result<()> {
+    let mut db = rusqlite::Connection::open(path)?;
+    db.execute_batch(
+        "CREATE TABLE IF NOT EXISTS pages(
+            url TEXT PRIMARY KEY,
+            depth INT,
+            status INT,
+            fetched_at TEXT
+        );
+        CREATE TABLE IF NOT EXISTS matches(url TEXT, snippet TEXT, line_num INT);",
+    )?;
+    let tx = db.transaction()?;
+    {
+        // A page may be reached by many paths,
+        // but we only store the shallowest.
+        // We don't know when pages from the cache were fetched.
+        let mut insert_page = tx.prepare(
+            "INSERT INTO pages(url, depth, status, fetched_at)
+            VALUES (?1, ?2, ?3, datetime(?4, 'unixepoch'))
+            ON CONFLICT(url) DO UPDATE SET depth = min(depth, excluded.depth)",
+        )?;
+        let mut insert_match =
+            tx.prepare("INSERT INTO matches(url, snippet, line_num) VALUES (?1, ?2, ?3)")?;
+        for page in &summary.crawl {
+            if let Some(url) = page.path.last() {
+                insert_page.execute(rusqlite::params![
+                    url.as_str(),
+                    page.depth as i64,
+                    page.status,
+                    page.fetched_at
+                        .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                        .map(|x| x.as_secs() as i64),
+                ])?;
+                if let Some(snippet) = &page.snippet {
+                    insert_match.execute(rusqlite::params![
+                        url.as_str(),
+                        snippet.text,
+                        snippet.line as i64
+                    ])?;
+                }
+            }
+        }
+    }
+    tx.commit()
+}
+
+fn display_path(path: &[Url]) -> String {
+    path.iter().map(|x| x.as_str()).join(" > ")
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
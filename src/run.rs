@@ -1,33 +1,301 @@
 use crate::cache::Cache;
-use crate::client::{Client, Response};
+use crate::client::{self, Client, Response};
 use crate::node::Node;
-use crate::run::page::Page;
+use crate::pattern::PatternEngine;
+use crate::run::page::Output as PageOutput;
+pub use crate::run::page::Page;
+use bloomfilter::Bloom;
+use dashmap::DashSet;
+use itertools::Itertools;
 use regex::Regex;
 use reqwest::Url;
+use std::cmp::Ordering as CmpOrdering;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::num::NonZeroUsize;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
-pub enum TaskResult<L: Client + 'static> {
-    Page(crate::run::page::RunTicket),
-    Request(crate::run::request::RunTicket<L>),
+/// Analytics and ad-tracking domains,
+/// never worth crawling
+const TRACKER_DOMAINS: &[&str] = &[
+    "2mdn.net",
+    "adnxs.com",
+    "adsrvr.org",
+    "amplitude.com",
+    "chartbeat.com",
+    "criteo.com",
+    "doubleclick.net",
+    "facebook.net",
+    "fullstory.com",
+    "google-analytics.com",
+    "googleadservices.com",
+    "googlesyndication.com",
+    "googletagmanager.com",
+    "hotjar.com",
+    "mixpanel.com",
+    "mouseflow.com",
+    "optimizely.com",
+    "quantserve.com",
+    "scorecardresearch.com",
+    "segment.com",
+    "segment.io",
+];
+
+pub struct Config {
+    /// Minimum delay between requests to a host
+    pub request_delay: Duration,
+    /// Maximum delay between requests to a host,
+    /// to randomize delays
+    /// between `request_delay` and this
+    pub max_request_delay: Option<Duration>,
+    /// Delays between requests to hosts,
+    /// by domain,
+    /// instead of `request_delay`
+    pub host_delays: HashMap<String, Duration>,
+    /// Read `Crawl-delay` from each host's `robots.txt`,
+    /// and wait at least that long between requests to it
+    pub respect_crawl_delay: bool,
+    /// Request each page with `HEAD` before `GET`,
+    /// skip `GET` if `HEAD` fails,
+    /// and record pages that refuse `HEAD`
+    /// but answer `GET`
+    pub probe_methods: bool,
+    /// Depths of starting URLs,
+    /// instead of 0,
+    /// so fewer links are followed from them
+    pub seed_depths: HashMap<Url, u64>,
+    /// Maximum requests per second to all hosts
+    pub rate_limit: Option<f64>,
+    /// Order to start queued pages and requests in
+    pub node_priority: NodePriority,
+    /// Maximum pages to parse and search at once,
+    /// independent of how many requests are in flight
+    pub page_threads: NonZeroUsize,
+    /// Maximum pages waiting to be searched,
+    /// dropping the lowest priority pages beyond it,
+    /// or `None` for no limit
+    pub queue_limit: Option<usize>,
+    pub exclude_urls_re: Option<Regex>,
+    /// Skip links to these domains
+    /// and their subdomains,
+    /// known trackers by default
+    pub blocked_domains: Vec<String>,
+    /// Links to follow from a starting URL,
+    /// or `None` for no limit
+    pub max_depth: Option<u64>,
+    /// Path segments a followed URL may have,
+    /// like 2 for `/a/b`,
+    /// or `None` for no limit
+    pub max_url_depth: Option<usize>,
+    pub search_re: PatternEngine,
+    /// Patterns a page must also match,
+    /// or, if `any_pattern`,
+    /// may match instead
+    pub extra_search_res: Vec<PatternEngine>,
+    pub any_pattern: bool,
+    pub invert_match: bool,
+    pub replace: Option<String>,
+    /// Print the text captured by this named group of `search_re`
+    /// for each match,
+    /// instead of the path to its page
+    pub extract: Option<String>,
+    /// Print each extracted value once,
+    /// across all pages
+    pub unique_extracts: bool,
+    /// Print each match of `search_re`
+    /// after the path to its page
+    pub only_matching: bool,
+    /// Stop searching a page
+    /// after this many matches,
+    /// with `replace`, `extract`, or `only_matching`
+    pub max_matches_per_page: Option<NonZeroUsize>,
+    /// Template for each printed match,
+    /// see `wg --help`
+    pub format: Option<String>,
+    pub strip_query: bool,
+    pub strip_params: Vec<String>,
+    pub skip_visited: bool,
+    pub exact_visited: bool,
+    pub follow_css_links: bool,
+    pub follow_resource_links: bool,
+    pub search_scripts: bool,
+    /// Search string values of JSON,
+    /// instead of raw JSON
+    pub search_json_values: bool,
+    /// Search keys of JSON objects,
+    /// instead of raw JSON
+    pub search_json_keys: bool,
+    pub follow_srcset: bool,
+    pub link_attrs: Vec<String>,
+    /// Also follow `<link rel="alternate">` pages
+    /// with an `hreflang` in these languages,
+    /// like `es`,
+    /// which also matches regions,
+    /// like `es-MX`
+    pub follow_hreflang: Vec<String>,
+    /// Print each link followed from a page,
+    /// like `source\ttarget`,
+    /// instead of matches
+    pub list_links: bool,
+    pub exclude_tags: Vec<String>,
+    pub include_tags: Vec<String>,
+    /// Also search values of HTML attributes with these names,
+    /// like `alt`
+    pub include_html_attrs: Vec<String>,
+    /// Ignore blocks of HTML that are mostly links,
+    /// like navigation bars and footers
+    pub readability: bool,
+    /// Search only the title, author, subject, and keywords of PDFs,
+    /// not their text
+    pub pdf_metadata_only: bool,
+    /// Password to open encrypted PDFs
+    pub pdf_password: Option<String>,
+    pub bloom_capacity: usize,
+    pub bloom_fp_rate: f64,
+    pub record_crawl: bool,
+    /// Print skipped links to stderr
+    pub verbose: bool,
+    /// How to print errors to stderr
+    pub error_format: ErrorFormat,
+    /// Treat every uncached page as empty,
+    /// instead of requesting it,
+    /// and record its URL
+    pub dry_run: bool,
+    /// Post each match as JSON here,
+    /// instead of writing it
+    pub webhook: Option<Webhook>,
+    /// Record pages with an HTTP error status,
+    /// besides searching them
+    pub record_dead_links: bool,
+    /// Warn when pages at different URLs
+    /// have the same text
+    pub detect_duplicates: bool,
+    /// Color matches with ANSI escape codes
+    pub color: bool,
+    /// Set to stop following links
+    /// and return after in-flight pages are searched
+    pub shutdown: AtomicBool,
+}
+
+impl Config {
+    pub fn new(search_re: impl Into<PatternEngine>) -> Self {
+        Self {
+            request_delay: Duration::from_secs(1),
+            max_request_delay: None,
+            host_delays: HashMap::new(),
+            respect_crawl_delay: false,
+            probe_methods: false,
+            seed_depths: HashMap::new(),
+            rate_limit: None,
+            node_priority: NodePriority::default(),
+            // Tokio uses number of CPU cores as default number of worker threads.
+            // `tokio::runtime::Handle::current().metrics().num_workers()`
+            // is only available in unstable Tokio.
+            // A larger buffer isn't necessary faster.
+            page_threads: std::thread::available_parallelism()
+                .unwrap_or(NonZeroUsize::new(1).unwrap()),
+            queue_limit: None,
+            exclude_urls_re: None,
+            blocked_domains: TRACKER_DOMAINS.iter().map(|&x| x.to_owned()).collect(),
+            max_depth: Some(1),
+            max_url_depth: None,
+            search_re: search_re.into(),
+            extra_search_res: Vec::new(),
+            any_pattern: false,
+            invert_match: false,
+            replace: None,
+            extract: None,
+            unique_extracts: false,
+            only_matching: false,
+            max_matches_per_page: None,
+            format: None,
+            strip_query: false,
+            strip_params: Vec::new(),
+            skip_visited: false,
+            exact_visited: false,
+            follow_css_links: false,
+            follow_resource_links: false,
+            search_scripts: false,
+            search_json_values: false,
+            search_json_keys: false,
+            follow_srcset: false,
+            link_attrs: Vec::new(),
+            follow_hreflang: Vec::new(),
+            list_links: false,
+            exclude_tags: vec!["head".to_owned(), "script".to_owned()],
+            include_tags: Vec::new(),
+            include_html_attrs: Vec::new(),
+            readability: false,
+            pdf_metadata_only: false,
+            pdf_password: None,
+            bloom_capacity: 1_000_000,
+            bloom_fp_rate: 0.01,
+            record_crawl: false,
+            verbose: false,
+            error_format: ErrorFormat::default(),
+            dry_run: false,
+            webhook: None,
+            record_dead_links: false,
+            detect_duplicates: false,
+            color: false,
+            shutdown: AtomicBool::new(false),
+        }
+    }
 }
 
-#[allow(clippy::too_many_arguments)]
 pub async fn run(
+    match_writer: impl Write,
+    progress: indicatif::MultiProgress,
+    cache: Arc<impl Cache<Url, Response> + Send + Sync + 'static>,
+    client: &'static (impl Client + Sync),
+    config: &'static Config,
+    urls: Vec<Url>,
+) -> Result<Summary, Error> {
+    run_with_matches(match_writer, progress, cache, client, config, urls, |_| {}).await
+}
+
+/// Like `run`,
+/// but also call `on_match` with each matching page.
+pub(crate) async fn run_with_matches(
     mut match_writer: impl Write,
     progress: indicatif::MultiProgress,
-    cache: &'static (impl Cache<Url, Response> + Sync),
+    cache: Arc<impl Cache<Url, Response> + Send + Sync + 'static>,
     client: &'static (impl Client + Sync),
-    request_delay: Duration,
-    page_threads: NonZeroUsize,
-    exclude_urls_re: &'static Option<Regex>,
-    max_depth: u64,
-    search_re: &'static Regex,
+    config: &'static Config,
     urls: Vec<Url>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    mut on_match: impl FnMut(&CrawledPage),
+) -> Result<Summary, Error> {
+    let start = Instant::now();
+    let mut summary = Summary::default();
+
+    // Without a global set of visited URLs,
+    // the same URL may be fetched and searched
+    // from many different branches.
+    let visited = if !config.skip_visited {
+        None
+    } else if config.exact_visited {
+        Some(Arc::new(VisitedSet::Exact(DashSet::new())))
+    } else {
+        Some(Arc::new(VisitedSet::Bloom(Mutex::new(
+            Bloom::new_for_fp_rate(config.bloom_capacity, config.bloom_fp_rate)
+                .map_err(Error::Bloom)?,
+        ))))
+    };
+
+    // Every starting URL is one page of work,
+    // even if no links are followed,
+    // so the progress bar must count them
+    // after skipping visited URLs.
+    let urls: Vec<_> = urls
+        .into_iter()
+        .filter(|u| is_unvisited(&visited, u))
+        .collect();
+
     let progress_style = indicatif::ProgressStyle::default_bar()
-        .template("{wide_bar} {pos:>7}/{len:<7} {msg}")
+        .template("{wide_bar} {pos:>7}/{len:<7} {per_sec:>12} {eta:>4} {msg}")
         .unwrap();
     let pages_progress = progress.add(
         indicatif::ProgressBar::new(urls.len().try_into().unwrap_or(0))
@@ -42,100 +310,597 @@ pub async fn run(
             .with_finish(indicatif::ProgressFinish::AndLeave),
     );
 
-    let mut tasks = tokio::task::JoinSet::new();
+    let mut requests = tokio::task::JoinSet::new();
+
+    // Matches being posted to `Config::webhook`
+    let mut deliveries = tokio::task::JoinSet::new();
+
+    // The first URL with each text hash,
+    // if `Config::detect_duplicates`
+    let mut text_hashes: HashMap<u64, Url> = HashMap::new();
+
+    // Values already printed,
+    // if `Config::unique_extracts`
+    let mut seen_extracts: HashSet<String> = HashSet::new();
 
-    let mut page_runner =
-        crate::run::page::Runner::new(cache, max_depth, search_re, exclude_urls_re, page_threads);
+    let (pages, mut page_outputs) =
+        crate::run::page::Runner::new(cache.clone(), config, visited.clone()).start();
+    // Pages sent to `pages`,
+    // or found in the cache by its runner,
+    // without an output yet
+    let mut pending_pages: u64 = 0;
 
     let mut request_runner =
-        crate::run::request::Runner::new(cache, client, request_delay, &progress);
+        crate::run::request::Runner::new(cache.clone(), client, config, &progress);
 
     urls.into_iter().for_each(|u| match cache.get(&u) {
-        Some(Ok(body)) => page_runner.push(&mut tasks, Node::new(None, Page::new(u, body))),
-        Some(Err(_)) => pages_progress.inc(1),
+        Some(Ok(fetched)) => {
+            summary.cache_hits += 1;
+            pending_pages += 1;
+            send_page(
+                &pages,
+                Node::with_depth(seed_depth(config, &u), Page::new(u, fetched)),
+            );
+        }
+        Some(Err(e)) => {
+            summary.cache_hits += 1;
+            record_error(&mut summary, config, &progress, &u, || vec![u.clone()], &e);
+            pages_progress.inc(1);
+        }
         None => {
             requests_progress.inc_length(1);
-            request_runner.push(&mut tasks, None, u);
+            request_runner.push(&mut requests, None, u);
         }
     });
-    while let Some(res) = tasks.join_one().await.unwrap() {
-        match res {
-            TaskResult::Page(ticket) => {
-                pages_progress.inc(1);
-                let (match_data, children_data) = page_runner.redeem(&mut tasks, ticket);
-
-                if let Some(s) = match_data {
-                    tokio::task::block_in_place(|| {
-                        progress.suspend(|| {
-                            match_writer
-                                .write_all(s.as_bytes())
-                                .and_then(|_| match_writer.write_all(b"\n"))
-                                .and_then(|_| match_writer.flush())
-                                .expect("Failed to print match");
-                        })
-                    });
-                };
+    loop {
+        tokio::select! {
+            Some(output) = page_outputs.recv(), if pending_pages > 0 => match output {
+                PageOutput::Page(x) => {
+                    let (mut ticket, (match_data, crawled_page, children_data)) = *x;
+                    pending_pages -= 1;
+                    pages_progress.inc(1);
+                    pages_progress.set_message(format!("Pages    {}", ticket.url));
+                    summary.pages += 1;
+                    if let Some(hash) = ticket.text_hash {
+                        match text_hashes.entry(hash) {
+                            Entry::Occupied(x) if *x.get() != ticket.url => progress.suspend(|| {
+                                eprintln!(
+                                    "Duplicate: {} has the same text as {}",
+                                    ticket.url,
+                                    x.get()
+                                )
+                            }),
+                            Entry::Occupied(_) => {}
+                            Entry::Vacant(x) => {
+                                x.insert(ticket.url.clone());
+                            }
+                        }
+                    }
+                    if let Some(s) = ticket.link_lines.take() {
+                        write_line(&mut match_writer, &progress, &s);
+                    }
+                    for u in std::mem::take(&mut ticket.skipped_links) {
+                        progress.suspend(|| eprintln!("Skipped link: {}", u));
+                    }
+                    if let Some(warning) = ticket.warning.take() {
+                        progress.suspend(|| eprintln!("Warning: {}: {}", ticket.url, warning));
+                    }
+                    summary.dead_links.extend(ticket.dead_link.take());
 
-                if let Some((good_cache_hits, bad_cache_hits, (parent, urls))) = children_data {
-                    pages_progress.inc_length(
-                        (good_cache_hits + urls.len()).try_into().unwrap_or(0) + bad_cache_hits,
-                    );
-                    pages_progress.inc(bad_cache_hits);
-                    requests_progress.inc_length(urls.len().try_into().unwrap_or(0));
-                    request_runner.extend(&mut tasks, &parent, urls);
+                    if let Some(page) = crawled_page {
+                        if page.is_match {
+                            on_match(&page);
+                        }
+                        if let Some(webhook) = config.webhook.as_ref().filter(|_| page.is_match) {
+                            deliveries.spawn(deliver(
+                                client,
+                                webhook,
+                                webhook_payload(&page),
+                                config.error_format,
+                                progress.clone(),
+                            ));
+                        }
+                        if config.record_crawl {
+                            summary.crawl.push(page);
+                        }
+                    }
+
+                    if let Some(s) = match_data {
+                        summary.matches += 1;
+                        if !config.list_links && config.webhook.is_none() {
+                            if config.unique_extracts {
+                                let s = s
+                                    .lines()
+                                    .filter(|x| seen_extracts.insert((*x).to_owned()))
+                                    .join("\n");
+                                if !s.is_empty() {
+                                    write_line(&mut match_writer, &progress, &s);
+                                }
+                            } else {
+                                write_line(&mut match_writer, &progress, &s);
+                            }
+                        }
+                    };
+
+                    if let Some((good_cache_hits, bad_cache_hits, (parent, urls))) = children_data {
+                        pending_pages += good_cache_hits as u64;
+                        pages_progress.inc_length(
+                            (good_cache_hits + bad_cache_hits.len() + urls.len())
+                                .try_into()
+                                .unwrap_or(0),
+                        );
+                        pages_progress.inc(bad_cache_hits.len().try_into().unwrap_or(0));
+                        summary.cache_hits += (good_cache_hits + bad_cache_hits.len())
+                            .try_into()
+                            .unwrap_or(0);
+                        for (url, e) in bad_cache_hits {
+                            record_error(
+                                &mut summary,
+                                config,
+                                &progress,
+                                &url,
+                                || crate::run::page::url_path(Some(parent.as_ref()), &url),
+                                &e,
+                            );
+                        }
+                        requests_progress.inc_length(urls.len().try_into().unwrap_or(0));
+                        request_runner.extend(&mut requests, &parent, urls);
+                    };
+                }
+                PageOutput::Dropped(n) => {
+                    pending_pages -= n;
+                    pages_progress.inc(n);
+                    summary.dropped += n;
+                }
+                PageOutput::Failed(e) => {
+                    pending_pages -= 1;
+                    pages_progress.inc(1);
+                    print_task_error(config, &progress, &e);
+                }
+            },
+            Some(res) = requests.join_next() => {
+                // A panicked task loses its request,
+                // but we would rather keep searching
+                // than lose every other page.
+                let ticket = match res {
+                    Ok(ticket) => ticket,
+                    Err(e) => {
+                        print_task_error(config, &progress, &e);
+                        continue;
+                    }
                 };
-            }
-            TaskResult::Request(ticket) => {
                 requests_progress.inc(1);
-                match request_runner.redeem(&mut tasks, ticket) {
-                    Ok(page) => page_runner.push(&mut tasks, page),
-                    Err(_) => pages_progress.inc(1),
+                summary.requests += 1;
+                if let Some(url) = ticket.get_only() {
+                    summary.get_only.push(url.clone());
+                }
+                // After shutdown,
+                // we finish what we started,
+                // but start nothing new.
+                let page = if config.shutdown.load(Ordering::Relaxed) {
+                    request_runner.finish(ticket)
+                } else {
+                    request_runner.redeem(&mut requests, ticket)
+                };
+                if config.dry_run {
+                    if let Ok(page) = &page {
+                        summary.would_fetch.push(Queued::url(page).clone());
+                    }
+                }
+                match page {
+                    Ok(page) => {
+                        pending_pages += 1;
+                        send_page(&pages, page);
+                    }
+                    Err(failed) => {
+                        let crate::run::request::FailedRequest(parent, url, e) = *failed;
+                        record_error(
+                            &mut summary,
+                            config,
+                            &progress,
+                            &url,
+                            || crate::run::page::url_path(parent.as_deref(), &url),
+                            &e,
+                        );
+                        pages_progress.inc(1);
+                    }
+                }
+            },
+            else => break,
+        }
+    }
+
+    while deliveries.join_next().await.is_some() {}
+
+    summary.elapsed = start.elapsed();
+    Ok(summary)
+}
+
+#[derive(Debug)]
+pub struct Webhook {
+    pub url: Url,
+    /// Value of the `Authorization` header,
+    /// like `Bearer TOKEN`
+    pub authorization: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// Prose,
+    /// without pages that failed to load
+    #[default]
+    Text,
+    /// A JSON object per line,
+    /// including pages that failed to load
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NodePriority {
+    /// Fewest links from a starting URL first
+    ShallowFirst,
+    /// Most links from a starting URL first
+    #[default]
+    DeepFirst,
+    /// By URL
+    Alphabetical,
+}
+
+/// A page or request
+/// waiting to start.
+trait Queued {
+    /// Links from a starting URL
+    fn depth(&self) -> u64;
+    fn url(&self) -> &Url;
+}
+
+/// A queued value,
+/// ordered by `NodePriority`
+/// with ties broken by URL,
+/// so `BinaryHeap` pops the value to start next.
+#[derive(Debug)]
+struct Prioritized<T> {
+    priority: NodePriority,
+    value: T,
+}
+
+impl<T> Prioritized<T> {
+    fn new(priority: NodePriority, value: T) -> Self {
+        Self { priority, value }
+    }
+
+    fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Queued> Ord for Prioritized<T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        // `BinaryHeap` pops the greatest value,
+        // so lesser URLs are greater.
+        let by_url = || other.value.url().cmp(self.value.url());
+        match self.priority {
+            NodePriority::ShallowFirst => other
+                .value
+                .depth()
+                .cmp(&self.value.depth())
+                .then_with(by_url),
+            NodePriority::DeepFirst => self
+                .value
+                .depth()
+                .cmp(&other.value.depth())
+                .then_with(by_url),
+            NodePriority::Alphabetical => by_url(),
+        }
+    }
+}
+
+impl<T: Queued> PartialOrd for Prioritized<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: Queued> Eq for Prioritized<T> {}
+
+impl<T: Queued> PartialEq for Prioritized<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("invalid Bloom filter parameters: {0}")]
+    Bloom(&'static str),
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Number of pages searched
+    pub pages: u64,
+    /// Number of pages that failed to load
+    pub errors: u64,
+    /// Number of pages matching `Config::search_re`
+    pub matches: u64,
+    /// Number of pages and errors loaded from the cache
+    pub cache_hits: u64,
+    /// Number of pages requested from the web
+    pub requests: u64,
+    /// Pages that would have been requested,
+    /// if `Config::dry_run`
+    pub would_fetch: Vec<Url>,
+    /// Number of pages dropped from the queue,
+    /// for `Config::queue_limit`
+    pub dropped: u64,
+    pub elapsed: Duration,
+    /// Every page searched,
+    /// if `Config::record_crawl`
+    pub crawl: Vec<CrawledPage>,
+    /// Every page with an HTTP error status,
+    /// if `Config::record_dead_links`
+    pub dead_links: Vec<DeadLink>,
+    /// Pages that refused `HEAD`
+    /// but answered `GET`,
+    /// if `Config::probe_methods`
+    pub get_only: Vec<Url>,
+}
+
+#[derive(Debug)]
+pub struct DeadLink {
+    /// URLs from a starting URL to this page
+    pub path: Vec<Url>,
+    pub status: u16,
+}
+
+#[derive(Debug)]
+pub struct CrawledPage {
+    /// URLs from a starting URL to this page
+    pub path: Vec<Url>,
+    /// Links from a starting URL to this page,
+    /// plus the depth of that starting URL
+    pub depth: u64,
+    /// HTTP status this page came with
+    pub status: u16,
+    /// When this page was fetched,
+    /// if not from the cache
+    pub fetched_at: Option<SystemTime>,
+    pub is_match: bool,
+    /// First match,
+    /// if `is_match`
+    pub snippet: Option<Snippet>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    /// Line of searched text containing the start of `text`,
+    /// starting from 1
+    pub line: usize,
+}
+
+pub type VisitedUrls = Option<Arc<VisitedSet>>;
+
+pub enum VisitedSet {
+    // A Bloom filter keeps memory usage constant,
+    // at the cost of occasionally skipping an unvisited URL.
+    Bloom(Mutex<Bloom<Url>>),
+    Exact(DashSet<Url>),
+}
+
+impl VisitedSet {
+    /// Mark `url` visited,
+    /// and return whether it was unvisited.
+    fn insert(&self, url: &Url) -> bool {
+        match self {
+            VisitedSet::Bloom(x) => !x.lock().unwrap().check_and_set(url),
+            VisitedSet::Exact(x) => x.insert(url.clone()),
+        }
+    }
+}
+
+const WEBHOOK_ATTEMPTS: u32 = 3;
+
+fn webhook_payload(page: &CrawledPage) -> String {
+    serde_json::json!({
+        "matched_url": page.path.last().map(Url::as_str),
+        "path": page.path.iter().map(Url::as_str).collect::<Vec<_>>(),
+        "snippet": page.snippet.as_ref().map(|x| x.text.as_str()),
+    })
+    .to_string()
+}
+
+// A webhook may be briefly unavailable,
+// so we try again,
+// waiting longer after each failure.
+async fn deliver(
+    client: &impl Client,
+    webhook: &Webhook,
+    body: String,
+    error_format: ErrorFormat,
+    progress: indicatif::MultiProgress,
+) {
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=WEBHOOK_ATTEMPTS {
+        match client
+            .post_json(&webhook.url, webhook.authorization.as_deref(), body.clone())
+            .await
+        {
+            Ok(()) => return,
+            Err(e) if attempt == WEBHOOK_ATTEMPTS => match error_format {
+                ErrorFormat::Text => {
+                    progress.suspend(|| eprintln!("Failed to post match to {}: {}", webhook.url, e))
                 }
+                ErrorFormat::Json => {
+                    print_json_error(&progress, "webhook_error", Some(&webhook.url), &e)
+                }
+            },
+            Err(_) => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
             }
         }
     }
+}
+
+fn write_line(w: &mut impl Write, progress: &indicatif::MultiProgress, s: &str) {
+    tokio::task::block_in_place(|| {
+        progress.suspend(|| {
+            w.write_all(s.as_bytes())
+                .and_then(|_| w.write_all(b"\n"))
+                .and_then(|_| w.flush())
+                .expect("Failed to print match");
+        })
+    });
+}
+
+// Pages often fail to load,
+// so they are only printed as JSON,
+// for tools to sift through.
+fn record_error(
+    summary: &mut Summary,
+    config: &Config,
+    progress: &indicatif::MultiProgress,
+    url: &Url,
+    path: impl FnOnce() -> Vec<Url>,
+    error: &client::Error,
+) {
+    summary.errors += 1;
+    if config.error_format == ErrorFormat::Json {
+        print_json_error(progress, "fetch_error", Some(url), error);
+    }
+    // Pages with error statuses are usually searched,
+    // and recorded with their tickets,
+    // but some clients fail them without a body.
+    if let client::Error::Other(client::ReqwestError::Status(status @ 400..)) = error {
+        if config.record_dead_links {
+            summary.dead_links.push(DeadLink {
+                path: path(),
+                status: *status,
+            });
+        }
+    }
+}
+
+fn send_page(pages: &tokio::sync::mpsc::UnboundedSender<Node<Page>>, page: Node<Page>) {
+    // The page runner only stops
+    // after `pages` is dropped.
+    pages.send(page).expect("page runner stopped early");
+}
+
+fn print_task_error(
+    config: &Config,
+    progress: &indicatif::MultiProgress,
+    e: &tokio::task::JoinError,
+) {
+    match config.error_format {
+        ErrorFormat::Text => progress.suspend(|| eprintln!("Task failed: {}", e)),
+        ErrorFormat::Json => print_json_error(progress, "task_error", None, e),
+    }
+}
+
+/// Print an error to stderr
+/// like `{"type": KIND, "url": URL, "error": ERROR}`.
+fn print_json_error(
+    progress: &indicatif::MultiProgress,
+    kind: &str,
+    url: Option<&Url>,
+    error: &dyn std::fmt::Display,
+) {
+    let x = serde_json::json!({
+        "type": kind,
+        "url": url.map(Url::as_str),
+        "error": error.to_string(),
+    });
+    progress.suspend(|| eprintln!("{}", x));
+}
+
+fn seed_depth(config: &Config, url: &Url) -> u64 {
+    config.seed_depths.get(url).copied().unwrap_or(0)
+}
 
-    Ok(())
+/// Return whether `url` has not been visited,
+/// and mark it visited.
+fn is_unvisited(visited: &VisitedUrls, url: &Url) -> bool {
+    visited.as_ref().is_none_or(|x| x.insert(url))
 }
 
 mod request {
     use crate::cache::Cache;
-    use crate::client::{self, Client, Response};
+    use crate::client::{self, Body, Client, Fetched, Response};
     use crate::node::{Node, NodeParent};
     use crate::run::page::Page;
-    use crate::run::TaskResult;
+    use crate::run::{seed_depth, Config, Prioritized, Queued};
     use indicatif::{MultiProgress, ProgressStyle};
+    use rand::Rng;
     use reqwest::Url;
-    use std::cmp::Ordering;
     use std::collections::BinaryHeap;
     use std::collections::HashMap;
-    use std::sync::Arc;
-    use std::time::{Duration, Instant};
+    use std::fmt;
+    use std::ops::RangeInclusive;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant, SystemTime};
     use tokio::task::JoinSet;
     use url::Host::{Domain, Ipv4, Ipv6};
 
     pub struct Runner<'a, C: Cache<Url, Response> + 'static, L: Client + 'static> {
-        cache: &'static C,
-        delay: Duration,
+        cache: Arc<C>,
+        config: &'static Config,
+        delay: RangeInclusive<Duration>,
+        host_delays: HashMap<String, Duration>,
+        rate_limit: Option<Arc<RateLimit>>,
         host_resources: HostResources<L>,
         master_client: &'static L,
         progress: &'a MultiProgress,
         spinner_style: ProgressStyle,
     }
 
-    type HostResources<L> = HashMap<String, (BinaryHeap<RequestUrl>, ClientSlot<L>)>;
+    // The cache, clients, and progress bars
+    // have nothing useful to show.
+    impl<C: Cache<Url, Response>, L: Client> fmt::Debug for Runner<'_, C, L> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Runner")
+                .field("delay", &self.delay)
+                .field("host_delays", &self.host_delays)
+                .field("rate_limit", &self.rate_limit)
+                .field(
+                    "queued_urls",
+                    &self
+                        .host_resources
+                        .iter()
+                        .map(|(host, (urls, _))| (host, urls.len()))
+                        .collect::<HashMap<_, _>>(),
+                )
+                .finish_non_exhaustive()
+        }
+    }
+
+    type HostResources<L> = HashMap<String, (BinaryHeap<Prioritized<RequestUrl>>, ClientSlot<L>)>;
     type ClientSlot<L> = Option<SlowClient<'static, L>>;
 
-    impl<'a, C: Cache<Url, Response> + Sync, L: Client + Sync> Runner<'a, C, L> {
+    impl<'a, C: Cache<Url, Response> + Send + Sync, L: Client + Sync> Runner<'a, C, L> {
         pub fn new(
-            cache: &'static C,
+            cache: Arc<C>,
             client: &'static L,
-            delay: Duration,
+            config: &'static Config,
             progress: &'a MultiProgress,
         ) -> Self {
             Self {
                 cache,
-                delay,
+                config,
+                delay: config.request_delay
+                    ..=config
+                        .max_request_delay
+                        .map_or(config.request_delay, |x| x.max(config.request_delay)),
+                host_delays: config
+                    .host_delays
+                    .iter()
+                    .map(|(host, delay)| (small_domain(host).to_owned(), *delay))
+                    .collect(),
+                rate_limit: config
+                    .rate_limit
+                    .and_then(|x| Duration::try_from_secs_f64(1.0 / x).ok())
+                    .map(|x| Arc::new(RateLimit::new(x))),
                 host_resources: HashMap::new(),
                 master_client: client,
                 progress,
@@ -147,13 +912,18 @@ mod request {
 
         pub fn redeem(
             &mut self,
-            join_set: &mut JoinSet<TaskResult<L>>,
+            join_set: &mut JoinSet<RunTicket<L>>,
             ticket: RunTicket<L>,
-        ) -> Result<Node<Page>, client::Error> {
-            let (host, client) = ticket.1;
+        ) -> Result<Node<Page>, Box<FailedRequest>> {
+            let RunTicket {
+                result,
+                host,
+                client,
+                ..
+            } = ticket;
             match self.host_resources.get_mut(&host) {
-                Some((urls, holding_space)) => match urls.pop() {
-                    Some(RequestUrl(p, u)) => self.spawn(join_set, host, client, p, u),
+                Some((urls, holding_space)) => match urls.pop().map(Prioritized::into_inner) {
+                    Some(RequestUrl(p, u, _)) => self.spawn(join_set, host, client, p, u),
                     None => {
                         debug_assert!(holding_space.is_none());
                         _ = holding_space.insert(client);
@@ -161,12 +931,18 @@ mod request {
                 },
                 None => panic!("Host resource invariant failed"),
             }
-            ticket.0
+            result
+        }
+
+        /// Like `redeem`,
+        /// but without starting queued requests.
+        pub fn finish(&self, ticket: RunTicket<L>) -> Result<Node<Page>, Box<FailedRequest>> {
+            ticket.result
         }
 
         pub fn extend(
             &mut self,
-            join_set: &mut JoinSet<TaskResult<L>>,
+            join_set: &mut JoinSet<RunTicket<L>>,
             parent: &Arc<Node<Page>>,
             urls: Vec<Url>,
         ) {
@@ -189,7 +965,7 @@ mod request {
 
         pub fn push(
             &mut self,
-            join_set: &mut JoinSet<TaskResult<L>>,
+            join_set: &mut JoinSet<RunTicket<L>>,
             parent: NodeParent<Page>,
             url: Url,
         ) {
@@ -207,14 +983,33 @@ mod request {
                         debug_assert!(urls.is_empty());
                         self.spawn(join_set, host.to_owned(), c, parent, url)
                     }
-                    None => urls.push(RequestUrl(parent, url)),
+                    None => {
+                        let depth = match &parent {
+                            Some(x) => x.depth() + 1,
+                            None => seed_depth(self.config, &url),
+                        };
+                        urls.push(Prioritized::new(
+                            self.config.node_priority,
+                            RequestUrl(parent, url, depth),
+                        ))
+                    }
                 },
                 None => {
                     let host_ = host.to_owned();
                     self.spawn(
                         join_set,
                         host_.clone(),
-                        SlowClient::new(self.master_client, self.delay),
+                        SlowClient::new(
+                            self.master_client,
+                            match self.host_delays.get(host) {
+                                Some(x) => *x..=*x,
+                                None => self.delay.clone(),
+                            },
+                            self.rate_limit.clone(),
+                        )
+                        .with_dry_run(self.config.dry_run)
+                        .with_crawl_delay(self.config.respect_crawl_delay)
+                        .with_probe_methods(self.config.probe_methods),
                         parent,
                         url,
                     );
@@ -225,7 +1020,7 @@ mod request {
 
         fn spawn(
             &self,
-            join_set: &mut JoinSet<TaskResult<L>>,
+            join_set: &mut JoinSet<RunTicket<L>>,
             host: String,
             mut client: SlowClient<'static, L>,
             parent: NodeParent<Page>,
@@ -236,88 +1031,110 @@ mod request {
                     .with_style(self.spinner_style.clone())
                     .with_message(url.to_string()),
             );
-            let cache = self.cache;
+            let cache = self.cache.clone();
+            let depth = seed_depth(self.config, &url);
             join_set.spawn(async move {
                 spinner.enable_steady_tick(Duration::from_millis(100));
-                TaskResult::Request(RunTicket(
-                    get_with_cache(cache, &mut client, &url)
-                        .await
-                        .map(|body| Node::new(parent, Page::new(url, body))),
-                    (host, client),
-                ))
+                let (response, latency, head_status) =
+                    get_with_cache(&*cache, &mut client, &url).await;
+                // Some servers refuse `HEAD`
+                // for pages they would `GET`.
+                let get_only = matches!(head_status, Some(405 | 501))
+                    && response.as_ref().is_ok_and(|x| !x.is_error());
+                RunTicket {
+                    result: match response {
+                        Ok(fetched) => {
+                            // Only pages from the web have a latency.
+                            let page = Page::new(url.clone(), fetched)
+                                .with_latency(latency)
+                                .with_fetched_at(latency.map(|_| SystemTime::now()));
+                            Ok(match parent {
+                                Some(_) => Node::new(parent, page),
+                                None => Node::with_depth(depth, page),
+                            })
+                        }
+                        Err(e) => Err(Box::new(FailedRequest(parent, url.clone(), e))),
+                    },
+                    get_only: get_only.then_some(url),
+                    host,
+                    client,
+                }
             });
         }
     }
 
-    pub struct RunTicket<L: Client + 'static>(
-        Result<Node<Page>, client::Error>,
-        (String, SlowClient<'static, L>),
-    );
-
-    struct RequestUrl(NodeParent<Page>, Url);
-
-    impl Ord for RequestUrl {
-        fn cmp(&self, other: &Self) -> Ordering {
-            match &self.0 {
-                Some(x) => match &other.0 {
-                    Some(y) => x.depth().cmp(&y.depth()),
-                    None => Ordering::Greater,
-                },
-                None => match other.0 {
-                    Some(_) => Ordering::Less,
-                    None => Ordering::Equal,
-                },
-            }
-        }
+    pub struct RunTicket<L: Client + 'static> {
+        result: Result<Node<Page>, Box<FailedRequest>>,
+        /// The requested URL,
+        /// if it refused `HEAD`
+        /// but answered `GET`
+        get_only: Option<Url>,
+        host: String,
+        client: SlowClient<'static, L>,
     }
 
-    impl PartialOrd for RequestUrl {
-        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-            Some(self.cmp(other))
+    impl<L: Client> RunTicket<L> {
+        /// The requested URL,
+        /// if it refused `HEAD`
+        /// but answered `GET`
+        pub fn get_only(&self) -> Option<&Url> {
+            self.get_only.as_ref()
         }
     }
 
-    impl Eq for RequestUrl {}
+    pub struct FailedRequest(pub NodeParent<Page>, pub Url, pub client::Error);
 
-    impl PartialEq for RequestUrl {
-        fn eq(&self, other: &Self) -> bool {
-            match &self.0 {
-                Some(x) => match &other.0 {
-                    Some(y) => x.depth() == y.depth(),
-                    None => false,
-                },
-                None => other.0.is_none(),
-            }
+    /// A URL waiting for its host,
+    /// with its depth
+    struct RequestUrl(NodeParent<Page>, Url, u64);
+
+    impl Queued for RequestUrl {
+        fn depth(&self) -> u64 {
+            self.2
+        }
+
+        fn url(&self) -> &Url {
+            &self.1
         }
     }
 
     fn small_host_name(url: &Url) -> &str {
         match url.host() {
-            Some(Domain(x)) => {
-                match x.rmatch_indices('.').nth(1) {
-                    // Slice is safe,
-                    // because `.` is one byte
-                    // `rmatch_indices` always returns valid indices,
-                    // and there will always be at least one character
-                    // after the second match from the right.
-                    Some((i, _)) => unsafe { x.get_unchecked(i + 1..) },
-                    None => x,
-                }
-            }
+            Some(Domain(x)) => small_domain(x),
             Some(Ipv4(_)) => url.host_str().unwrap(),
             Some(Ipv6(_)) => url.host_str().unwrap(),
             None => "",
         }
     }
 
+    fn small_domain(x: &str) -> &str {
+        match x.rmatch_indices('.').nth(1) {
+            // Slice is safe,
+            // because `.` is one byte
+            // `rmatch_indices` always returns valid indices,
+            // and there will always be at least one character
+            // after the second match from the right.
+            Some((i, _)) => unsafe { x.get_unchecked(i + 1..) },
+            None => x,
+        }
+    }
+
+    /// Get `url`,
+    /// how long the request took,
+    /// if not from the cache,
+    /// and the status of a `HEAD` request before it,
+    /// if probed.
     async fn get_with_cache<'a>(
         cache: &impl Cache<Url, Response>,
         client: &mut SlowClient<'a, impl Client>,
         url: &Url,
-    ) -> Response {
+    ) -> (Response, Option<Duration>, Option<u16>) {
         match cache.get(url) {
-            Some(x) => x,
-            None => get_and_cache_from_web(cache, client, url).await,
+            Some(x) => (x, None, None),
+            None => {
+                let (body, latency, head_status) = get_and_cache_from_web(cache, client, url).await;
+                (body, Some(latency), head_status)
+            }
         }
     }
 
@@ -325,34 +1142,148 @@ mod request {
         cache: &impl Cache<Url, Response>,
         client: &mut SlowClient<'a, impl Client>,
         url: &Url,
-    ) -> Response {
-        let body = client.get(url).await;
+    ) -> (Response, Duration, Option<u16>) {
+        let head_status = client.head(url).await;
+        // Some servers refuse `HEAD`,
+        // with 405 or 501,
+        // for pages they would `GET`.
+        // We don't know what `GET` would return,
+        // so we don't cache the failure
+        // under `url`.
+        if let Some(status) = head_status.filter(|x| *x >= 400 && !matches!(x, 405 | 501)) {
+            return (
+                Err(client::Error::Other(client::ReqwestError::Status(status))),
+                Duration::ZERO,
+                head_status,
+            );
+        }
+
+        let (body, latency) = client.get(url).await;
 
         // We would rather keep searching
         // than panic
         // or delay
         // from failed caching.
-        let _ = cache.set(url, &body);
+        // Pages from a dry run aren't real,
+        // and some errors only hold for these options.
+        if !client.dry_run && !body.as_ref().is_err_and(client::Error::is_from_options) {
+            let _ = cache.set(url, &body);
+        }
 
-        body
+        (body, latency, head_status)
     }
 
     pub struct SlowClient<'a, L: Client> {
         client: &'a L,
-        delay: Duration,
+        delay: RangeInclusive<Duration>,
+        next_delay: Duration,
         last_request_finished: Option<Instant>,
+        rate_limit: Option<Arc<RateLimit>>,
+        dry_run: bool,
+        /// Read `Crawl-delay` before the next request
+        check_crawl_delay: bool,
+        probe_methods: bool,
+    }
+
+    impl<L: Client> fmt::Debug for SlowClient<'_, L> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("SlowClient")
+                .field("delay", &self.delay)
+                .field("next_delay", &self.next_delay)
+                .field("last_request_finished", &self.last_request_finished)
+                .field("rate_limit", &self.rate_limit)
+                .field("dry_run", &self.dry_run)
+                .field("check_crawl_delay", &self.check_crawl_delay)
+                .field("probe_methods", &self.probe_methods)
+                .finish_non_exhaustive()
+        }
     }
 
     impl<'a, L: Client> SlowClient<'a, L> {
-        pub fn new(client: &'a L, delay: Duration) -> Self {
+        pub fn new(
+            client: &'a L,
+            delay: RangeInclusive<Duration>,
+            rate_limit: Option<Arc<RateLimit>>,
+        ) -> Self {
             Self {
                 client,
+                next_delay: *delay.start(),
                 delay,
                 last_request_finished: None,
+                rate_limit,
+                dry_run: false,
+                check_crawl_delay: false,
+                probe_methods: false,
+            }
+        }
+
+        /// Return an empty page for every URL,
+        /// without waiting,
+        /// instead of requesting it.
+        pub fn with_dry_run(self, dry_run: bool) -> Self {
+            Self { dry_run, ..self }
+        }
+
+        /// Before the first request,
+        /// get `robots.txt` from its host,
+        /// and wait at least its `Crawl-delay`
+        /// between requests.
+        pub fn with_crawl_delay(self, check_crawl_delay: bool) -> Self {
+            Self {
+                check_crawl_delay,
+                ..self
+            }
+        }
+
+        /// Send `HEAD` requests from `head`,
+        /// instead of skipping them.
+        pub fn with_probe_methods(self, probe_methods: bool) -> Self {
+            Self {
+                probe_methods,
+                ..self
+            }
+        }
+
+        /// Get `url`,
+        /// and how long the request took,
+        /// not counting the delay before it.
+        pub async fn get(&mut self, url: &Url) -> (Response, Duration) {
+            if self.dry_run {
+                return (
+                    Ok(Fetched {
+                        status: 200,
+                        body: Body::Plain(String::new()),
+                    }),
+                    Duration::ZERO,
+                );
+            }
+            self.wait(url).await;
+            let start = Instant::now();
+            let body = self.client.get(url).await;
+            let now = Instant::now();
+            self.finish_request(now);
+            (body, now - start)
+        }
+
+        /// Status of a `HEAD` request for `url`,
+        /// if `probe_methods`
+        /// and it was sent.
+        pub async fn head(&mut self, url: &Url) -> Option<u16> {
+            if !self.probe_methods || self.dry_run {
+                return None;
             }
+            self.wait(url).await;
+            let status = self.client.head(url).await;
+            self.finish_request(Instant::now());
+            // `GET` may still work
+            // if `HEAD` fails to send.
+            status.ok()
         }
 
-        pub async fn get(&mut self, url: &Url) -> Response {
+        async fn wait(&mut self, url: &Url) {
+            if std::mem::take(&mut self.check_crawl_delay) {
+                self.read_crawl_delay(url).await;
+            }
             // Making web requests
             // at the speed of a computer
             // can have negative repercussions,
@@ -361,96 +1292,368 @@ mod request {
             if time_remaining > Duration::ZERO {
                 tokio::time::sleep(time_remaining).await;
             }
-            let body = self.client.get(url).await;
-            self.last_request_finished = Some(Instant::now());
-            body
+            if let Some(x) = &self.rate_limit {
+                x.wait().await;
+            }
+        }
+
+        fn finish_request(&mut self, now: Instant) {
+            self.last_request_finished = Some(now);
+            // Requests at regular intervals
+            // look like a bot.
+            self.next_delay = rand::thread_rng().gen_range(self.delay.clone());
         }
 
         pub fn time_remaining(&self) -> Duration {
             self.last_request_finished
-                .and_then(|x| self.delay.checked_sub(x.elapsed()))
+                .and_then(|x| self.next_delay.checked_sub(x.elapsed()))
                 .unwrap_or(Duration::ZERO)
         }
+
+        async fn read_crawl_delay(&mut self, url: &Url) {
+            let Ok(robots_url) = url.join("/robots.txt") else {
+                return;
+            };
+            if let Some(x) = &self.rate_limit {
+                x.wait().await;
+            }
+            let robots = self.client.get(&robots_url).await;
+            self.last_request_finished = Some(Instant::now());
+            // Most hosts have no `robots.txt`,
+            // or no `Crawl-delay` in it,
+            // so we keep our own delay.
+            if let Some(crawl_delay) =
+                robots
+                    .ok()
+                    .filter(|x| !x.is_error())
+                    .and_then(|x| match x.body {
+                        Body::Plain(text) => crawl_delay(&text),
+                        _ => None,
+                    })
+            {
+                let start = (*self.delay.start()).max(crawl_delay);
+                let end = (*self.delay.end()).max(start);
+                self.delay = start..=end;
+                self.next_delay = self.next_delay.max(start);
+            }
+        }
+    }
+
+    /// `Crawl-delay` for all user agents,
+    /// from the group for `User-agent: *`
+    /// in `robots.txt`
+    fn crawl_delay(robots: &str) -> Option<Duration> {
+        // A group starts with one or more `User-agent` lines.
+        let mut in_agents = false;
+        let mut for_all = false;
+        for line in robots.lines() {
+            let line = line.split('#').next().unwrap_or("");
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            if key.eq_ignore_ascii_case("user-agent") {
+                if !in_agents {
+                    for_all = false;
+                }
+                in_agents = true;
+                for_all |= value == "*";
+            } else {
+                in_agents = false;
+                if for_all && key.eq_ignore_ascii_case("crawl-delay") {
+                    return value
+                        .parse()
+                        .ok()
+                        .and_then(|x| Duration::try_from_secs_f64(x).ok());
+                }
+            }
+        }
+        None
+    }
+
+    /// Space requests to all hosts
+    /// at least `interval` apart.
+    #[derive(Debug)]
+    pub struct RateLimit {
+        interval: Duration,
+        next_request: Mutex<Instant>,
+    }
+
+    impl RateLimit {
+        pub fn new(interval: Duration) -> Self {
+            Self {
+                interval,
+                next_request: Mutex::new(Instant::now()),
+            }
+        }
+
+        async fn wait(&self) {
+            // Each request reserves the next slot,
+            // so waiting requests don't all start at once.
+            let time_remaining = {
+                let mut next_request = self.next_request.lock().unwrap();
+                let now = Instant::now();
+                let start = (*next_request).max(now);
+                *next_request = start + self.interval;
+                start - now
+            };
+            if time_remaining > Duration::ZERO {
+                tokio::time::sleep(time_remaining).await;
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn host(url: &str) -> String {
+            small_host_name(&Url::parse(url).unwrap()).to_owned()
+        }
+
+        #[test]
+        fn small_host_name_keeps_domains() {
+            assert_eq!(host("http://example.com/"), "example.com");
+        }
+
+        #[test]
+        fn small_host_name_strips_subdomains() {
+            assert_eq!(host("http://sub.example.com/"), "example.com");
+        }
+
+        #[test]
+        fn small_host_name_strips_nested_subdomains() {
+            assert_eq!(host("http://a.b.c.example.com/"), "example.com");
+        }
+
+        #[test]
+        fn small_host_name_keeps_ipv4_addresses() {
+            assert_eq!(host("http://192.168.0.1/"), "192.168.0.1");
+        }
+
+        #[test]
+        fn small_host_name_keeps_ipv6_addresses() {
+            assert_eq!(host("http://[::1]/"), "[::1]");
+        }
+
+        #[test]
+        fn small_host_name_is_empty_without_a_host() {
+            assert_eq!(host("data:text/plain,foo"), "");
+        }
+
+        #[test]
+        fn crawl_delay_reads_group_for_all_agents() {
+            assert_eq!(
+                crawl_delay(
+                    "User-agent: Googlebot\nCrawl-delay: 1\n\nUser-agent: *\nDisallow: /private\nCrawl-delay: 2.5 # seconds\n"
+                ),
+                Some(Duration::from_millis(2500))
+            );
+        }
+
+        #[test]
+        fn crawl_delay_reads_groups_with_many_agents() {
+            assert_eq!(
+                crawl_delay("User-agent: Bingbot\nUser-agent: *\nCrawl-delay: 3"),
+                Some(Duration::from_secs(3))
+            );
+        }
+
+        #[test]
+        fn crawl_delay_ignores_other_agents() {
+            assert_eq!(crawl_delay("User-agent: Bingbot\nCrawl-delay: 3"), None);
+        }
+
+        #[test]
+        fn crawl_delay_ignores_invalid_delays() {
+            assert_eq!(crawl_delay("User-agent: *\nCrawl-delay: soon"), None);
+        }
     }
 }
 
+// Benchmarks need page parsing internals.
+#[doc(hidden)]
+pub use crate::run::page::{inner_text, links};
+
 mod page {
     use crate::cache::Cache;
-    use crate::client::{Body, Client, Response};
-    use crate::node::{path_to_root, Node};
-    use crate::run::TaskResult;
+    use crate::client::{self, Body, Fetched, Response};
+    use crate::node::Node;
+    use crate::run::{
+        is_unvisited, Config, CrawledPage, DeadLink, Prioritized, Queued, Snippet, VisitedUrls,
+    };
     use html5ever::tendril::TendrilSink;
+    use html5ever::Attribute;
     use itertools::Itertools;
     use markup5ever_rcdom::{Handle, NodeData, RcDom};
+    use quick_xml::escape::resolve_predefined_entity;
+    use quick_xml::events::Event;
     use regex::Regex;
     use reqwest::Url;
-    use std::cmp::Ordering;
+    use std::borrow::Cow;
     use std::collections::BinaryHeap;
-    use std::collections::HashSet;
+    use std::collections::{HashMap, HashSet};
     use std::default::Default;
+    use std::fmt;
     use std::num::NonZeroUsize;
-    use std::ops::Deref;
-    use std::sync::Arc;
-    use tokio::task::JoinSet;
+    use std::rc::Rc;
+    use std::sync::atomic::Ordering;
+    use std::sync::{Arc, LazyLock};
+    use std::time::{Duration, SystemTime};
+    use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+    use tokio::task::{JoinError, JoinSet};
 
     pub struct Runner<C: Cache<Url, Response> + 'static> {
-        cache: &'static C,
-        max_depth: u64,
-        search_re: &'static Regex,
-        exclude_urls_re: &'static Option<Regex>,
+        cache: Arc<C>,
+        config: &'static Config,
+        visited: VisitedUrls,
         max_tasks: usize,
         num_tasks: usize,
-        queue: BinaryHeap<PageNode>,
+        queue: BinaryHeap<Prioritized<Node<Page>>>,
+        /// Pages dropped from `queue`
+        /// since the last `Output::Dropped`
+        dropped: u64,
     }
 
-    impl<C: Cache<Url, Response> + Sync> Runner<C> {
-        pub fn new(
-            cache: &'static C,
-            max_depth: u64,
-            search_re: &'static Regex,
-            exclude_urls_re: &'static Option<Regex>,
-            max_tasks: NonZeroUsize,
-        ) -> Self {
+    impl<C: Cache<Url, Response>> fmt::Debug for Runner<C> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("Runner")
+                .field("max_tasks", &self.max_tasks)
+                .field("num_tasks", &self.num_tasks)
+                .field("queue", &self.queue)
+                .field("dropped", &self.dropped)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// What became of pages sent to a `Runner`
+    pub enum Output {
+        /// A searched page,
+        /// with pages linked from it
+        /// counted in `RunOutput`
+        Page(Box<(RunTicket, RunOutput)>),
+        /// Pages dropped from the queue,
+        /// for `Config::queue_limit`
+        Dropped(u64),
+        /// A page lost to a panicked task
+        Failed(JoinError),
+    }
+
+    impl<C: Cache<Url, Response> + Send + Sync + 'static> Runner<C> {
+        pub fn new(cache: Arc<C>, config: &'static Config, visited: VisitedUrls) -> Self {
             Self {
                 cache,
-                max_depth,
-                search_re,
-                exclude_urls_re,
-                max_tasks: max_tasks.get(),
+                config,
+                visited,
+                max_tasks: config.page_threads.get(),
                 num_tasks: 0,
                 queue: BinaryHeap::new(),
+                dropped: 0,
             }
         }
 
-        pub fn redeem(
-            &mut self,
-            join_set: &mut JoinSet<TaskResult<impl Client + Sync>>,
-            ticket: RunTicket,
-        ) -> RunOutput {
-            self.num_tasks -= 1;
+        /// Search pages from the returned sender
+        /// until it is dropped
+        /// and every page is searched,
+        /// sending what became of each page,
+        /// and each page it links to in the cache,
+        /// to the returned receiver.
+        pub fn start(self) -> (UnboundedSender<Node<Page>>, UnboundedReceiver<Output>) {
+            // Bounded channels could deadlock,
+            // with each side waiting to send to the other.
+            let (page_sender, pages) = mpsc::unbounded_channel();
+            let (output_sender, outputs) = mpsc::unbounded_channel();
+            tokio::spawn(self.run(pages, output_sender));
+            (page_sender, outputs)
+        }
+
+        async fn run(
+            mut self,
+            mut pages: UnboundedReceiver<Node<Page>>,
+            outputs: UnboundedSender<Output>,
+        ) {
+            let mut join_set = JoinSet::new();
+            let mut receiving = true;
+            loop {
+                tokio::select! {
+                    page = pages.recv(), if receiving => match page {
+                        Some(page) => self.push(&mut join_set, page),
+                        None => receiving = false,
+                    },
+                    Some(res) = join_set.join_next() => {
+                        let output = match res {
+                            Ok(mut ticket) => {
+                                // After shutdown,
+                                // we finish what we started,
+                                // but start nothing new.
+                                let output = if self.config.shutdown.load(Ordering::Relaxed) {
+                                    self.finish(&mut join_set, &mut ticket)
+                                } else {
+                                    self.redeem(&mut join_set, &mut ticket)
+                                };
+                                Output::Page(Box::new((ticket, output)))
+                            }
+                            Err(e) => {
+                                self.num_tasks -= 1;
+                                self.spawn_next(&mut join_set);
+                                Output::Failed(e)
+                            }
+                        };
+                        if outputs.send(output).is_err() {
+                            break;
+                        }
+                    }
+                    else => break,
+                }
+                let dropped = std::mem::take(&mut self.dropped);
+                if dropped > 0 && outputs.send(Output::Dropped(dropped)).is_err() {
+                    break;
+                }
+            }
+        }
+
+        fn redeem(
+            &mut self,
+            join_set: &mut JoinSet<RunTicket>,
+            ticket: &mut RunTicket,
+        ) -> RunOutput {
+            self.num_tasks -= 1;
             (
-                ticket.0,
-                match ticket.1 {
+                ticket.match_data.take(),
+                ticket.crawled_page.take(),
+                match ticket.children.take() {
                     Some((pages, bad_cache_hits, request_data)) => {
                         let good_cache_hits = pages.len();
                         self.extend(join_set, pages);
                         Some((good_cache_hits, bad_cache_hits, request_data))
                     }
                     None => {
-                        if let Some(page) = self.queue.pop() {
-                            self.spawn(join_set, page.into());
-                        }
+                        self.spawn_next(join_set);
                         None
                     }
                 },
             )
         }
 
-        fn extend(
+        /// Like `redeem`,
+        /// but without following links.
+        fn finish(
             &mut self,
-            join_set: &mut JoinSet<TaskResult<impl Client + Sync>>,
-            pages: Vec<Node<Page>>,
-        ) {
+            join_set: &mut JoinSet<RunTicket>,
+            ticket: &mut RunTicket,
+        ) -> RunOutput {
+            self.num_tasks -= 1;
+            self.spawn_next(join_set);
+            (ticket.match_data.take(), ticket.crawled_page.take(), None)
+        }
+
+        fn spawn_next(&mut self, join_set: &mut JoinSet<RunTicket>) {
+            if let Some(page) = self.queue.pop() {
+                self.spawn(join_set, page.into_inner());
+            }
+        }
+
+        fn extend(&mut self, join_set: &mut JoinSet<RunTicket>, pages: Vec<Node<Page>>) {
             // We want to add as many pages as possible
             // before picking the best pages
             // to start as tasks,
@@ -465,244 +1668,866 @@ mod page {
                     self.spawn(join_set, page);
                 }
                 while let Some(page) = self.queue.pop() {
-                    self.spawn(join_set, page.into());
+                    self.spawn(join_set, page.into_inner());
                 }
                 debug_assert!(self.queue.is_empty());
             } else {
-                self.queue.extend(pages.into_iter().map(|page| page.into()));
+                self.queue.extend(
+                    pages
+                        .into_iter()
+                        .map(|page| Prioritized::new(self.config.node_priority, page)),
+                );
                 for _ in 0..n {
                     match self.queue.pop() {
-                        Some(page) => self.spawn(join_set, page.into()),
+                        Some(page) => self.spawn(join_set, page.into_inner()),
                         None => break,
                     }
                 }
                 debug_assert_eq!(self.num_tasks, self.max_tasks);
                 debug_assert!(!self.queue.is_empty());
+                self.limit_queue();
             }
         }
 
-        pub fn push(
-            &mut self,
-            join_set: &mut JoinSet<TaskResult<impl Client + Sync>>,
-            page: Node<Page>,
-        ) {
+        fn push(&mut self, join_set: &mut JoinSet<RunTicket>, page: Node<Page>) {
             if self.num_tasks < self.max_tasks {
                 debug_assert!(self.queue.is_empty());
                 self.spawn(join_set, page)
             } else {
-                self.queue.push(page.into())
+                self.queue
+                    .push(Prioritized::new(self.config.node_priority, page));
+                self.limit_queue();
             }
         }
 
-        fn spawn(
-            &mut self,
-            join_set: &mut JoinSet<TaskResult<impl Client + Sync>>,
-            page: Node<Page>,
-        ) {
+        /// Drop the lowest priority pages
+        /// beyond `Config::queue_limit`.
+        fn limit_queue(&mut self) {
+            if let Some(limit) = self.config.queue_limit.filter(|x| self.queue.len() > *x) {
+                let mut pages = std::mem::take(&mut self.queue).into_vec();
+                // Highest priority first
+                pages.select_nth_unstable_by(limit, |a, b| b.cmp(a));
+                self.dropped += (pages.len() - limit) as u64;
+                pages.truncate(limit);
+                self.queue = pages.into();
+            }
+        }
+
+        fn spawn(&mut self, join_set: &mut JoinSet<RunTicket>, page: Node<Page>) {
             self.num_tasks += 1;
-            let cache = self.cache;
-            let max_depth = self.max_depth;
-            let search_re = self.search_re;
-            let exclude_urls_re = self.exclude_urls_re;
-            join_set.spawn(async move {
-                TaskResult::Page(parse_page(
-                    cache,
-                    max_depth,
-                    search_re,
-                    exclude_urls_re,
-                    page,
-                ))
-            })
+            let cache = self.cache.clone();
+            let config = self.config;
+            let visited = self.visited.clone();
+            // Parsing and searching large pages
+            // would otherwise stall other tasks,
+            // like requests,
+            // on the same worker thread.
+            join_set.spawn_blocking(move || parse_page(&*cache, config, &visited, page));
         }
     }
 
-    pub struct RunTicket(
-        MatchData,
-        Option<(Vec<Node<Page>>, BadCacheHits, RequestData)>,
-    );
+    /// A searched page,
+    /// and what to do with it
+    pub struct RunTicket {
+        pub match_data: MatchData,
+        pub crawled_page: Option<CrawledPage>,
+        pub children: Option<(Vec<Node<Page>>, BadCacheHits, RequestData)>,
+        /// URL of the searched page
+        pub url: Url,
+        /// Hash of the text of the searched page,
+        /// if `Config::detect_duplicates`
+        pub text_hash: Option<u64>,
+        /// Links followed from the searched page,
+        /// a line per link,
+        /// if `Config::list_links`
+        pub link_lines: Option<String>,
+        /// Links skipped for their scheme,
+        /// like `mailto:`,
+        /// if `Config::verbose`
+        pub skipped_links: Vec<Url>,
+        /// Why the page couldn't be searched,
+        /// like an encrypted PDF
+        pub warning: Option<String>,
+        /// The searched page,
+        /// if it has an HTTP error status
+        /// and `Config::record_dead_links`
+        pub dead_link: Option<DeadLink>,
+    }
 
     pub type RunOutput = (
         MatchData,
+        Option<CrawledPage>,
         Option<(GoodCacheHits, BadCacheHits, RequestData)>,
     );
     pub type MatchData = Option<String>;
     pub type GoodCacheHits = usize;
-    pub type BadCacheHits = u64;
+    pub type BadCacheHits = Vec<(Url, client::Error)>;
     pub type RequestData = (Arc<Node<Page>>, Vec<Url>);
 
-    struct PageNode(Node<Page>);
-
-    impl Deref for PageNode {
-        type Target = Node<Page>;
+    impl Queued for Node<Page> {
+        fn depth(&self) -> u64 {
+            Node::depth(self)
+        }
 
-        fn deref(&self) -> &Self::Target {
-            &self.0
+        fn url(&self) -> &Url {
+            &self.value().url
         }
     }
 
-    impl From<Node<Page>> for PageNode {
-        fn from(page: Node<Page>) -> Self {
-            PageNode(page)
-        }
+    #[derive(Debug)]
+    pub struct Page {
+        url: Url,
+        /// HTTP status `body` came with
+        status: u16,
+        body: Body,
+        /// Time to request `body`,
+        /// if not from the cache
+        latency: Option<Duration>,
+        /// When `body` was fetched,
+        /// if not from the cache
+        fetched_at: Option<SystemTime>,
     }
 
-    impl From<PageNode> for Node<Page> {
-        fn from(page: PageNode) -> Self {
-            page.0
+    impl Page {
+        pub fn new(url: Url, fetched: Fetched) -> Self {
+            Self {
+                url,
+                status: fetched.status,
+                body: fetched.body,
+                latency: None,
+                fetched_at: None,
+            }
         }
-    }
 
-    impl Ord for PageNode {
-        fn cmp(&self, other: &Self) -> Ordering {
-            self.depth().cmp(&other.depth())
+        pub fn with_latency(self, latency: Option<Duration>) -> Self {
+            Self { latency, ..self }
         }
-    }
 
-    impl PartialOrd for PageNode {
-        fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-            Some(self.cmp(other))
+        pub fn with_fetched_at(self, fetched_at: Option<SystemTime>) -> Self {
+            Self { fetched_at, ..self }
         }
-    }
 
-    impl Eq for PageNode {}
+        pub fn url(&self) -> &Url {
+            &self.url
+        }
 
-    impl PartialEq for PageNode {
-        fn eq(&self, other: &Self) -> bool {
-            self.depth() == other.depth()
+        pub fn status(&self) -> u16 {
+            self.status
         }
-    }
 
-    #[derive(Debug)]
-    pub struct Page {
-        url: Url,
-        body: Body,
-    }
+        pub fn body(&self) -> &Body {
+            &self.body
+        }
 
-    impl Page {
-        pub fn new(url: Url, body: Body) -> Self {
-            Self { url, body }
+        /// Time to request `body`,
+        /// if not from the cache
+        pub fn latency(&self) -> Option<Duration> {
+            self.latency
+        }
+
+        /// When `body` was fetched,
+        /// if not from the cache
+        pub fn fetched_at(&self) -> Option<SystemTime> {
+            self.fetched_at
         }
     }
 
+    // Pages move between tasks,
+    // and their parents are shared between tasks.
+    static_assertions::assert_impl_all!(Node<Page>: Send, Sync);
+
     fn parse_page(
         cache: &impl Cache<Url, Response>,
-        max_depth: u64,
-        search_re: &Regex,
-        exclude_urls_re: &Option<Regex>,
+        config: &Config,
+        visited: &VisitedUrls,
         node: Node<Page>,
     ) -> RunTicket {
-        match &node.value().body {
-            Body::Html(body) => {
-                match html5ever::parse_document(RcDom::default(), Default::default())
-                    .from_utf8()
-                    .read_from(&mut body.as_bytes())
-                    .ok()
-                {
-                    Some(dom) => {
+        let follow_links = config.max_depth.is_none_or(|x| node.depth() < x);
+        let dom = match &node.value().body {
+            Body::Html(body) => html5ever::parse_document(RcDom::default(), Default::default())
+                .from_utf8()
+                .read_from(&mut body.as_bytes())
+                .ok(),
+            _ => None,
+        };
+
+        // The same page is often reachable by many URLs,
+        // like with different query parameters,
+        // so we search it under its canonical URL,
+        // and follow its links once.
+        // Any page can name any URL canonical,
+        // so we only trust URLs on the page's own origin.
+        let url = node.value().url.clone();
+        let (node, follow_links) = match dom
+            .as_ref()
+            .and_then(|dom| canonical_url(&url, dom))
+            .filter(|x| *x != url && x.origin() == url.origin())
+        {
+            // Only the canonical URL is marked visited.
+            // This page's body isn't the canonical page's,
+            // so it isn't cached under the canonical URL.
+            Some(canonical) if is_unvisited(visited, &canonical) => (
+                node.map_value(|page| Page {
+                    url: canonical,
+                    ..page
+                }),
+                follow_links,
+            ),
+            // Misconfigured sites may name one page canonical for every page,
+            // so we still search this page,
+            // but leave its links to the canonical page.
+            Some(_) => (node, false),
+            None => (node, follow_links),
+        };
+
+        let mut warning = None;
+        let (text, links): (Option<Cow<str>>, Option<HashSet<Url>>) = match &node.value().body {
+            Body::Html(_) => {
+                match &dom {
+                    Some(dom) => (
                         // Matches may span DOM nodes,
                         // so we can't just check DOM nodes individually.
-                        let match_data = search_re
-                            .is_match(&inner_text(&dom))
-                            .then(|| display_node_path(&node));
-
-                        let children_data = if node.depth() < max_depth {
-                            let node_ = Arc::new(node);
-                            let node_path: HashSet<_> =
-                                path_to_root(&node_).map(|x| &x.url).collect();
-                            let mut children = Vec::new();
-                            let mut page_errors = 0;
-                            let mut urls = Vec::new();
-                            links(&node_.value().url, &dom)
-                                .into_iter()
-                                // We don't need to know if a path cycles back on itself.
-                                // For us,
-                                // path cycles waste time and lead to infinite loops.
-                                .filter(|u| !node_path.contains(&u))
-                                // We're hoping the Rust compiler optimizes this branch
-                                // out of the loop.
-                                .filter(|u| {
-                                    exclude_urls_re
-                                        .as_ref()
-                                        .map_or(true, |re| !re.is_match(u.as_str()))
-                                })
-                                .for_each(|u| match cache.get(&u) {
-                                    Some(Ok(body)) => children.push(Node::new(
-                                        Some(Arc::clone(&node_)),
-                                        Page::new(u, body),
-                                    )),
-                                    Some(Err(_)) => page_errors += 1,
-                                    None => urls.push(u),
-                                });
-                            Some((children, page_errors, (node_, urls)))
-                        } else {
-                            None
-                        };
-
-                        RunTicket(match_data, children_data)
-                    }
-                    None => RunTicket(None, None),
+                        Some(Cow::Owned(inner_text(dom, config))),
+                        follow_links.then(|| {
+                            let url = &node.value().url;
+                            links(base_url(url, dom).as_ref().unwrap_or(url), dom, config)
+                        }),
+                    ),
+                    None => (None, None),
                 }
             }
-            // TODO: decompress PDF if necessary.
-            Body::Pdf(raw) => RunTicket(
-                search_re.is_match(raw).then(|| display_node_path(&node)),
-                None,
+            Body::Pdf(raw) => match pdf_text(raw, config) {
+                Ok(text) => (Some(Cow::Owned(text)), None),
+                Err(e) => {
+                    warning = Some(e.to_owned());
+                    (None, None)
+                }
+            },
+            Body::Plain(text) => (Some(Cow::Borrowed(text)), None),
+            Body::Css(text) => (
+                Some(Cow::Borrowed(text)),
+                (follow_links && config.follow_css_links)
+                    .then(|| css_links(&node.value().url, text).collect()),
             ),
-            Body::Plain(text) => RunTicket(
-                search_re.is_match(text).then(|| display_node_path(&node)),
+            Body::Script(text) => (config.search_scripts.then_some(Cow::Borrowed(text)), None),
+            Body::Xml(xml) => (Some(Cow::Owned(xml_text(xml))), None),
+            Body::Markdown(text) => (Some(Cow::Owned(strip_markdown(text))), None),
+            Body::Json(json) => (
+                Some(
+                    match serde_json::from_str(json)
+                        .ok()
+                        .filter(|_| config.search_json_values || config.search_json_keys)
+                    {
+                        Some(value) => {
+                            let mut s = String::new();
+                            push_json_text(&mut s, &value, config);
+                            Cow::Owned(s)
+                        }
+                        None => Cow::Borrowed(json),
+                    },
+                ),
                 None,
             ),
+        };
+
+        // Links like `mailto:` resolve to valid URLs,
+        // but they can't be requested.
+        let (links, skipped_links) = match links {
+            Some(xs) => {
+                let (skipped, xs): (HashSet<_>, _) = xs
+                    .into_iter()
+                    .partition(|x| UNFOLLOWABLE_SCHEMES.contains(&x.scheme()));
+                let skipped = if config.verbose {
+                    skipped.into_iter().sorted_unstable().collect()
+                } else {
+                    Vec::new()
+                };
+                (Some(xs), skipped)
+            }
+            None => (None, Vec::new()),
+        };
+
+        // Pages we can't search
+        // match neither way.
+        let (is_match, snippet) = match &text {
+            Some(text) => {
+                let snippet = search(config, text);
+                if config.invert_match {
+                    (snippet.is_none(), None)
+                } else {
+                    (snippet.is_some(), snippet)
+                }
+            }
+            None => (false, None),
+        };
+
+        let match_data = is_match
+            .then(|| {
+                let path = DisplayPath {
+                    node: &node,
+                    color: config.color,
+                }
+                .to_string();
+                // With `replace`, `extract`, or `only_matching`,
+                // we print a line per match,
+                // instead of a line per page.
+                let per_match =
+                    config.replace.is_some() || config.extract.is_some() || config.only_matching;
+                let max_matches = config
+                    .max_matches_per_page
+                    .map_or(usize::MAX, NonZeroUsize::get);
+                let matches: Vec<_> = match (&config.replace, &text) {
+                    (Some(template), Some(text)) => replacements(config, template, text)
+                        .take(max_matches)
+                        .collect(),
+                    (None, Some(text)) if config.extract.is_some() => config
+                        .search_re
+                        .group_iter(config.extract.as_deref().unwrap(), text)
+                        .take(max_matches)
+                        .map(str::to_owned)
+                        .collect(),
+                    (None, Some(text)) if config.only_matching => config
+                        .search_re
+                        .find_iter(text)
+                        .take(max_matches)
+                        .map(|m| {
+                            if config.color {
+                                crate::format::highlight(m.as_str(), &config.search_re)
+                            } else {
+                                m.as_str().to_owned()
+                            }
+                        })
+                        .collect(),
+                    _ => vec![snippet
+                        .as_ref()
+                        .map_or_else(String::new, |x| x.text.clone())],
+                };
+                // A page can match
+                // with nothing to print per match,
+                // like with `any_pattern`
+                // and only an extra pattern matching,
+                // or an `extract` group that didn't participate.
+                (!matches.is_empty()).then(|| {
+                    matches
+                        .iter()
+                        .map(|m| match &config.format {
+                            Some(template) => crate::format::render(
+                                template,
+                                &crate::format::Fields {
+                                    url: &node.value().url,
+                                    path: &path,
+                                    depth: node.depth(),
+                                    matched: m,
+                                    latency: node.value().latency,
+                                },
+                            ),
+                            None if config.extract.is_some() => m.clone(),
+                            None if per_match => format!("{}: {}", path, m),
+                            None => path.clone(),
+                        })
+                        .join("\n")
+                })
+            })
+            .flatten();
+
+        let text_hash = text
+            .as_ref()
+            .filter(|_| config.detect_duplicates)
+            .map(|x| seahash::hash(x.as_bytes()));
+        let url = node.value().url.clone();
+        let link_lines = links
+            .as_ref()
+            .filter(|xs| config.list_links && !xs.is_empty())
+            .map(|xs| {
+                xs.iter()
+                    .map(Url::as_str)
+                    .sorted_unstable()
+                    .map(|x| format!("{}\t{}", url, x))
+                    .join("\n")
+            });
+        let dead_link =
+            (config.record_dead_links && node.value().status >= 400).then(|| DeadLink {
+                path: url_path(node.parent(), &node.value().url),
+                status: node.value().status,
+            });
+        RunTicket {
+            match_data,
+            // Matching pages are always passed to `on_match`.
+            crawled_page: (config.record_crawl || is_match).then(|| CrawledPage {
+                path: node
+                    .path_from_root()
+                    .into_iter()
+                    .map(|x| x.url.clone())
+                    .collect(),
+                depth: node.depth(),
+                status: node.value().status,
+                fetched_at: node.value().fetched_at,
+                is_match,
+                snippet,
+            }),
+            children: links.map(|urls| children(cache, config, visited, node, urls)),
+            url,
+            text_hash,
+            link_lines,
+            skipped_links,
+            warning,
+            dead_link,
         }
     }
 
-    fn display_node_path(node: &Node<Page>) -> String {
-        node.path_from_root()
-            .iter()
-            .map(|x| x.url.as_str())
-            .join(" > ")
+    fn search(config: &Config, text: &str) -> Option<Snippet> {
+        let m = if config.any_pattern {
+            std::iter::once(&config.search_re)
+                .chain(&config.extra_search_res)
+                .find_map(|re| re.find(text))
+        } else {
+            config
+                .search_re
+                .find(text)
+                .filter(|_| config.extra_search_res.iter().all(|re| re.is_match(text)))
+        }?;
+        Some(Snippet {
+            text: m.as_str().to_owned(),
+            line: text[..m.start()].matches('\n').count() + 1,
+        })
+    }
+
+    // Like `Regex::replace_all`,
+    // but only the replaced text.
+    fn replacements<'a>(
+        config: &'a Config,
+        template: &'a str,
+        text: &'a str,
+    ) -> impl Iterator<Item = String> + 'a {
+        config.search_re.expand_iter(template, text)
     }
 
-    fn inner_text(dom: &RcDom) -> String {
+    fn children(
+        cache: &impl Cache<Url, Response>,
+        config: &Config,
+        visited: &VisitedUrls,
+        node: Node<Page>,
+        links: HashSet<Url>,
+    ) -> (Vec<Node<Page>>, BadCacheHits, RequestData) {
+        let node_ = Arc::new(node);
+        let node_path: HashSet<_> = node_.ancestors().map(|x| &x.url).collect();
+        let mut children = Vec::new();
+        let mut page_errors = Vec::new();
+        let mut urls = Vec::new();
+        links
+            .into_iter()
+            .map(|mut u| {
+                if config.strip_query {
+                    u.set_query(None);
+                } else if !config.strip_params.is_empty() {
+                    strip_params(&mut u, &config.strip_params);
+                }
+                u
+            })
+            // Stripping queries may produce duplicate URLs.
+            .unique()
+            // We don't need to know if a path cycles back on itself.
+            // For us,
+            // path cycles waste time and lead to infinite loops.
+            .filter(|u| !node_path.contains(&u))
+            // We're hoping the Rust compiler optimizes this branch
+            // out of the loop.
+            .filter(|u| {
+                config
+                    .exclude_urls_re
+                    .as_ref()
+                    .is_none_or(|re| !re.is_match(u.as_str()))
+            })
+            .filter(|u| !is_blocked(u, &config.blocked_domains))
+            // Deeply nested paths are often generated,
+            // leading to the same content forever.
+            .filter(|u| config.max_url_depth.is_none_or(|x| url_depth(u) <= x))
+            // This filter must come last,
+            // because it marks URLs visited.
+            .filter(|u| is_unvisited(visited, u))
+            .for_each(|u| match cache.get(&u) {
+                Some(Ok(fetched)) => {
+                    children.push(Node::new(Some(Arc::clone(&node_)), Page::new(u, fetched)))
+                }
+                Some(Err(e)) => page_errors.push((u, e)),
+                None => urls.push(u),
+            });
+        (children, page_errors, (node_, urls))
+    }
+
+    fn is_blocked(url: &Url, domains: &[String]) -> bool {
+        url.domain().is_some_and(|host| {
+            domains.iter().any(|x| {
+                host.strip_suffix(x.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.ends_with('.'))
+            })
+        })
+    }
+
+    fn strip_params(url: &mut Url, params: &[String]) {
+        if let Some(query) = url.query() {
+            let query = query
+                .split('&')
+                .filter(|x| {
+                    let key = x.split_once('=').map_or(*x, |(k, _)| k);
+                    !params.iter().any(|p| p == key)
+                })
+                .join("&");
+            url.set_query(Some(query.as_str()).filter(|x| !x.is_empty()));
+        }
+    }
+
+    /// URLs from a starting URL to `url`,
+    /// through `parent`.
+    pub fn url_path(parent: Option<&Node<Page>>, url: &Url) -> Vec<Url> {
+        let mut path: Vec<_> = parent.map_or_else(Vec::new, |x| {
+            x.path_from_root()
+                .into_iter()
+                .map(|x| x.url.clone())
+                .collect()
+        });
+        path.push(url.clone());
+        path
+    }
+
+    /// Display URLs from a starting URL to a page,
+    /// like `a > b > c`,
+    /// with the page in red
+    /// and the rest dim,
+    /// if `color`.
+    struct DisplayPath<'a> {
+        node: &'a Node<Page>,
+        color: bool,
+    }
+
+    impl fmt::Display for DisplayPath<'_> {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            use crate::format::{DIM, MATCH, RESET};
+
+            fn write_ancestors(f: &mut fmt::Formatter<'_>, node: &Node<Page>) -> fmt::Result {
+                if let Some(parent) = node.parent() {
+                    write_ancestors(f, parent)?;
+                }
+                write!(f, "{} > ", node.value().url)
+            }
+
+            if let Some(parent) = self.node.parent() {
+                if self.color {
+                    f.write_str(DIM)?;
+                }
+                write_ancestors(f, parent)?;
+                if self.color {
+                    f.write_str(RESET)?;
+                }
+            }
+            if self.color {
+                write!(f, "{}{}{}", MATCH, self.node.value().url, RESET)
+            } else {
+                write!(f, "{}", self.node.value().url)
+            }
+        }
+    }
+
+    /// Text of a PDF,
+    /// after its title, author, subject, and keywords,
+    /// or only those if `Config::pdf_metadata_only`,
+    /// or an error if it is encrypted
+    /// and `Config::pdf_password` doesn't open it
+    fn pdf_text(raw: &[u8], config: &Config) -> Result<String, &'static str> {
+        let mut doc = match lopdf::Document::load_mem(raw) {
+            Ok(doc) => doc,
+            // Text may still be found in a PDF
+            // too broken to parse.
+            Err(_) if !config.pdf_metadata_only => {
+                return Ok(String::from_utf8_lossy(raw).into_owned())
+            }
+            Err(_) => return Ok(String::new()),
+        };
+        if doc.is_encrypted() {
+            // Many PDFs only restrict editing,
+            // and open with an empty password.
+            doc.decrypt(config.pdf_password.as_deref().unwrap_or(""))
+                .map_err(|_| match config.pdf_password {
+                    Some(_) => "skipped encrypted PDF, password didn't open it",
+                    None => "skipped encrypted PDF, no password given",
+                })?;
+        }
+        let info = doc
+            .trailer
+            .get(b"Info")
+            .and_then(|x| doc.dereference(x))
+            .and_then(|(_, x)| x.as_dict())
+            .ok();
+        let mut lines: Vec<_> = [&b"Title"[..], b"Author", b"Subject", b"Keywords"]
+            .into_iter()
+            .filter_map(|k| info?.get(k).and_then(|x| doc.dereference(x)).ok())
+            .filter_map(|(_, x)| lopdf::decode_text_string(x).ok())
+            .collect();
+        if !config.pdf_metadata_only {
+            let pages: Vec<_> = doc.get_pages().into_keys().collect();
+            lines.extend(doc.extract_text(&pages).ok());
+        }
+        Ok(lines.join("\n"))
+    }
+
+    // Like `inner_text`,
+    // but for XML.
+    fn xml_text(xml: &str) -> String {
+        let mut s = String::new();
+        let mut reader = quick_xml::Reader::from_str(xml);
+        loop {
+            match reader.read_event() {
+                Ok(Event::Text(x)) => s.push_str(&x.xml10_content()),
+                Ok(Event::CData(x)) => s.push_str(&x.xml10_content()),
+                Ok(Event::GeneralRef(x)) => match x.resolve_char_ref() {
+                    Ok(Some(c)) => s.push(c),
+                    _ => s.push_str(resolve_predefined_entity(&x).unwrap_or_default()),
+                },
+                // We would rather search what we parsed
+                // than nothing.
+                Ok(Event::Eof) | Err(_) => break,
+                _ => {}
+            }
+        }
+        s
+    }
+
+    // Each string is on its own line,
+    // so patterns can't match across them.
+    fn push_json_text(s: &mut String, value: &serde_json::Value, config: &Config) {
+        match value {
+            serde_json::Value::String(x) if config.search_json_values => {
+                s.push_str(x);
+                s.push('\n');
+            }
+            serde_json::Value::Array(xs) => {
+                for x in xs {
+                    push_json_text(s, x, config);
+                }
+            }
+            serde_json::Value::Object(xs) => {
+                for (k, x) in xs {
+                    if config.search_json_keys {
+                        s.push_str(k);
+                        s.push('\n');
+                    }
+                    push_json_text(s, x, config);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // `html5ever` decodes entities in text,
+    // so decoding again would turn text like `&amp;lt;`
+    // into `<`
+    // instead of `&lt;`.
+    pub fn inner_text(dom: &RcDom, config: &Config) -> String {
+        let mut lens = BlockTextLens::new();
+        if config.readability {
+            block_text_lens(&mut lens, &dom.document);
+        }
         let mut s = String::new();
+        push_inner_text(
+            &mut s,
+            config,
+            &lens,
+            config.include_tags.is_empty(),
+            &dom.document,
+        );
+        s
+    }
+
+    // If the user includes tags,
+    // we only keep text inside an included tag.
+    fn push_inner_text(
+        s: &mut String,
+        config: &Config,
+        lens: &BlockTextLens,
+        included: bool,
+        handle: &Handle,
+    ) {
         walk_dom(
-            &mut |data| {
-                match data {
-                    NodeData::Text { contents } => {
+            &mut |handle| {
+                match &handle.data {
+                    NodeData::Text { contents } if included => {
                         s.push_str(contents.borrow().as_ref());
                     }
-                    NodeData::Element { name, .. } => {
-                        // We want to search like a person viewing the page,
-                        // so we ignore invisible tags.
-                        if ["head", "script"].contains(&name.local.as_ref()) {
-                            return false;
+                    // We want to search like a person viewing the page,
+                    // so we ignore invisible tags
+                    // and any others the user excludes.
+                    NodeData::Element { name, .. }
+                        if config.exclude_tags.iter().any(|x| x == name.local.as_ref()) =>
+                    {
+                        return false;
+                    }
+                    NodeData::Element { name, .. }
+                        if config.readability
+                            && BLOCK_TAGS.contains(&name.local.as_ref())
+                            && is_boilerplate(lens, handle) =>
+                    {
+                        return false;
+                    }
+                    NodeData::Element { name, .. }
+                        if !included
+                            && config.include_tags.iter().any(|x| x == name.local.as_ref()) =>
+                    {
+                        push_inner_text(s, config, lens, true, handle);
+                        return false;
+                    }
+                    // Attribute values aren't next to text on the page,
+                    // so we keep them apart.
+                    NodeData::Element { attrs, .. }
+                        if included && !config.include_html_attrs.is_empty() =>
+                    {
+                        for x in attrs.borrow().iter().filter(|x| {
+                            config
+                                .include_html_attrs
+                                .iter()
+                                .any(|name| name == x.name.local.as_ref())
+                        }) {
+                            s.push(' ');
+                            s.push_str(&x.value);
+                            s.push(' ');
                         }
                     }
                     _ => {}
                 }
                 true
             },
-            &dom.document,
+            handle,
         );
-        s
+    }
+
+    const BLOCK_TAGS: &[&str] = &[
+        "article", "aside", "div", "footer", "header", "li", "main", "nav", "ol", "p", "section",
+        "table", "td", "tr", "ul",
+    ];
+
+    // Navigation bars,
+    // footers,
+    // and other boilerplate repeated across pages
+    // are mostly links,
+    // unlike content.
+    fn is_boilerplate(lens: &BlockTextLens, handle: &Handle) -> bool {
+        lens.get(&Rc::as_ptr(handle))
+            .is_some_and(|&(text_len, link_len)| text_len > 0 && link_len * 2 > text_len)
+    }
+
+    /// Non-whitespace characters of text,
+    /// and of text inside links,
+    /// in each block element,
+    /// by address
+    type BlockTextLens = HashMap<*const markup5ever_rcdom::Node, (usize, usize)>;
+
+    /// Count text in every block under `handle`,
+    /// in one pass,
+    /// because blocks nest,
+    /// and counting each separately
+    /// would count deep text once per block around it.
+    fn block_text_lens(lens: &mut BlockTextLens, handle: &Handle) -> (usize, usize) {
+        let sum = |lens: &mut BlockTextLens| {
+            handle
+                .children
+                .borrow()
+                .iter()
+                .map(|x| block_text_lens(lens, x))
+                .fold((0, 0), |(a, b), (c, d)| (a + c, b + d))
+        };
+        match &handle.data {
+            NodeData::Text { contents } => (
+                contents
+                    .borrow()
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .count(),
+                0,
+            ),
+            NodeData::Element { name, .. } => {
+                let (text_len, link_len) = sum(lens);
+                let x = if name.local.as_ref() == "a" {
+                    (text_len, text_len)
+                } else {
+                    (text_len, link_len)
+                };
+                if BLOCK_TAGS.contains(&name.local.as_ref()) {
+                    lens.insert(Rc::as_ptr(handle), x);
+                }
+                x
+            }
+            NodeData::Document => sum(lens),
+            _ => (0, 0),
+        }
+    }
+
+    /// Count non-empty path segments,
+    /// so `/a/b/` and `/a/b` are both 2 deep.
+    fn url_depth(url: &Url) -> usize {
+        url.path_segments()
+            .map_or(0, |xs| xs.filter(|x| !x.is_empty()).count())
     }
 
     // We only want unique links.
     // `HashSet` takes care of this.
-    fn links(origin: &Url, dom: &RcDom) -> HashSet<Url> {
+    pub fn links(origin: &Url, dom: &RcDom, config: &Config) -> HashSet<Url> {
         let mut xs = HashSet::new();
         walk_dom(
-            &mut |data| {
-                if let NodeData::Element { name, attrs, .. } = data {
-                    if name.local.as_ref() == "a" {
-                        attrs
-                            .borrow()
-                            .iter()
-                            .filter(|x| x.name.local.as_ref() == "href")
-                            .take(1) // An `a` tag shouldn't have more than one `href`
-                            .filter_map(|x| origin.join(&x.value).ok())
-                            .for_each(|x| {
-                                xs.insert(x);
-                            });
+            &mut |handle| {
+                if let NodeData::Element { name, attrs, .. } = &handle.data {
+                    let attrs = attrs.borrow();
+                    match name.local.as_ref() {
+                        "a" | "area" => {
+                            xs.extend(attr(&attrs, "href").and_then(|x| resolve(origin, x)))
+                        }
+                        "link"
+                            if config.follow_css_links
+                                && attr(&attrs, "rel").is_some_and(|x| {
+                                    x.split_ascii_whitespace()
+                                        .any(|x| x.eq_ignore_ascii_case("stylesheet"))
+                                }) =>
+                        {
+                            xs.extend(attr(&attrs, "href").and_then(|x| resolve(origin, x)))
+                        }
+                        "link"
+                            if attr(&attrs, "rel").is_some_and(|x| {
+                                x.split_ascii_whitespace()
+                                    .any(|x| x.eq_ignore_ascii_case("alternate"))
+                            }) && attr(&attrs, "hreflang")
+                                .is_some_and(|x| is_followed_lang(&config.follow_hreflang, x)) =>
+                        {
+                            xs.extend(attr(&attrs, "href").and_then(|x| resolve(origin, x)))
+                        }
+                        "script" | "img" | "iframe" | "source" if config.follow_resource_links => {
+                            xs.extend(attr(&attrs, "src").and_then(|x| resolve(origin, x)))
+                        }
+                        // Some sites navigate with soft redirects.
+                        "meta"
+                            if attr(&attrs, "http-equiv")
+                                .is_some_and(|x| x.eq_ignore_ascii_case("refresh")) =>
+                        {
+                            xs.extend(
+                                attr(&attrs, "content")
+                                    .and_then(refresh_url)
+                                    .and_then(|x| resolve(origin, x)),
+                            )
+                        }
+                        "style" if config.follow_css_links => {
+                            for child in handle.children.borrow().iter() {
+                                if let NodeData::Text { contents } = &child.data {
+                                    xs.extend(css_links(origin, &contents.borrow()));
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                    // Lazy-loading sites often put URLs in nonstandard attributes.
+                    if config.follow_srcset {
+                        if let Some(srcset) = attr(&attrs, "srcset") {
+                            xs.extend(srcset_urls(srcset).filter_map(|x| resolve(origin, x)));
+                        }
+                    }
+                    for name in &config.link_attrs {
+                        xs.extend(attr(&attrs, name).and_then(|x| resolve(origin, x)));
                     }
                 }
                 true
@@ -712,11 +2537,171 @@ mod page {
         xs
     }
 
+    // Language tags are case insensitive,
+    // and a language includes its regions,
+    // like `es-MX` for `es`.
+    fn is_followed_lang(langs: &[String], hreflang: &str) -> bool {
+        langs.iter().any(|lang| {
+            hreflang.eq_ignore_ascii_case(lang)
+                || hreflang
+                    .get(..lang.len())
+                    .is_some_and(|x| x.eq_ignore_ascii_case(lang))
+                    && hreflang[lang.len()..].starts_with('-')
+        })
+    }
+
+    // `srcset` looks like `foo.png 1x, bar.png 2x`.
+    fn srcset_urls(srcset: &str) -> impl Iterator<Item = &str> {
+        srcset
+            .split(',')
+            .filter_map(|x| x.split_ascii_whitespace().next())
+    }
+
+    // Per the HTML spec,
+    // the first `base` element with an `href`
+    // replaces the page URL
+    // when resolving relative links.
+    fn base_url(url: &Url, dom: &RcDom) -> Option<Url> {
+        let mut base = None;
+        walk_dom(
+            &mut |handle| {
+                if let NodeData::Element { name, attrs, .. } = &handle.data {
+                    // Returning `false` only skips children,
+                    // so later `base` elements still reach here.
+                    if name.local.as_ref() == "base" && base.is_none() {
+                        base = attr(&attrs.borrow(), "href").and_then(|x| url.join(x).ok());
+                    }
+                }
+                base.is_none()
+            },
+            &dom.document,
+        );
+        base
+    }
+
+    fn canonical_url(url: &Url, dom: &RcDom) -> Option<Url> {
+        let mut canonical = None;
+        walk_dom(
+            &mut |handle| {
+                if let NodeData::Element { name, attrs, .. } = &handle.data {
+                    let attrs = attrs.borrow();
+                    // Like `base_url`,
+                    // the first canonical link wins.
+                    if name.local.as_ref() == "link"
+                        && canonical.is_none()
+                        && attr(&attrs, "rel").is_some_and(|x| {
+                            x.split_ascii_whitespace()
+                                .any(|x| x.eq_ignore_ascii_case("canonical"))
+                        })
+                    {
+                        canonical = attr(&attrs, "href").and_then(|x| resolve(url, x));
+                    }
+                }
+                canonical.is_none()
+            },
+            &dom.document,
+        );
+        canonical
+    }
+
+    fn css_links<'a>(origin: &'a Url, css: &'a str) -> impl Iterator<Item = Url> + 'a {
+        static CSS_URL_RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r#"url\(\s*["']?([^"')\s]+)["']?\s*\)|@import\s+["']([^"']+)["']"#).unwrap()
+        });
+        CSS_URL_RE
+            .captures_iter(css)
+            .filter_map(|x| x.get(1).or_else(|| x.get(2)))
+            .filter_map(|x| resolve(origin, x.as_str()))
+    }
+
+    // A full Markdown parser would be more accurate,
+    // but we only need prose to search,
+    // not a faithful rendering.
+    fn strip_markdown(text: &str) -> String {
+        static FENCE_RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"(?m)^[ \t]*(?:```|~~~).*\n?").unwrap());
+        // Headings, block quotes, and list items
+        static PREFIX_RE: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(r"(?m)^[ \t]*(?:#{1,6}[ \t]+|>[ \t]?|[-*+][ \t]+|\d+\.[ \t]+)").unwrap()
+        });
+        static LINK_RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"!?\[([^\]]*)\]\([^)]*\)").unwrap());
+        static EMPHASIS_RE: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(r"\*\*|__|`|~~").unwrap());
+        let text = FENCE_RE.replace_all(text, "");
+        let text = PREFIX_RE.replace_all(&text, "");
+        let text = LINK_RE.replace_all(&text, "$1");
+        EMPHASIS_RE.replace_all(&text, "").into_owned()
+    }
+
+    // `content` looks like `5; url=https://example.com/`.
+    // We don't care how long a page waits to redirect.
+    fn refresh_url(content: &str) -> Option<&str> {
+        let (_, rest) = content.split_once([';', ','])?;
+        let rest = rest.trim_start();
+        let url = match rest.split_once('=') {
+            Some((key, value)) if key.trim_end().eq_ignore_ascii_case("url") => value,
+            _ => rest,
+        };
+        Some(url.trim().trim_matches(['"', '\''])).filter(|x| !x.is_empty())
+    }
+
+    // An element shouldn't have more than one attribute with the same name.
+    fn attr<'a>(attrs: &'a [Attribute], name: &str) -> Option<&'a str> {
+        attrs
+            .iter()
+            .find(|x| x.name.local.as_ref() == name)
+            .map(|x| x.value.as_ref())
+    }
+
+    const UNFOLLOWABLE_SCHEMES: &[&str] = &["data", "javascript", "mailto", "tel"];
+
+    fn resolve(origin: &Url, href: &str) -> Option<Url> {
+        origin.join(href).ok().map(normalize_url)
+    }
+
+    // Many URLs refer to the same page.
+    // Normalizing them
+    // lets `HashSet` and the cache
+    // catch more duplicates.
+    fn normalize_url(mut url: Url) -> Url {
+        // Fragments only refer to a part of a page.
+        url.set_fragment(None);
+
+        // Query parameter order rarely matters.
+        // We sort raw key-value pairs,
+        // instead of decoding and reencoding them,
+        // to avoid changing their meaning.
+        if let Some(query) = url.query() {
+            if query.is_empty() {
+                url.set_query(None);
+            } else {
+                let mut params: Vec<_> = query.split('&').collect();
+                params.sort_unstable();
+                let query = params.join("&");
+                url.set_query(Some(&query));
+            }
+        }
+
+        // `Url` already lowercases schemes,
+        // and hosts of special schemes like `http`,
+        // but not hosts of other schemes.
+        if let Some(host) = url
+            .host_str()
+            .filter(|x| x.bytes().any(|c| c.is_ascii_uppercase()))
+            .map(|x| x.to_ascii_lowercase())
+        {
+            let _ = url.set_host(Some(&host));
+        }
+
+        url
+    }
+
     fn walk_dom<F>(f: &mut F, handle: &Handle)
     where
-        F: FnMut(&NodeData) -> bool,
+        F: FnMut(&Handle) -> bool,
     {
-        if f(&handle.data) {
+        if f(handle) {
             if let NodeData::Element {
                 template_contents: Some(inner),
                 ..
@@ -729,4 +2714,329 @@ mod page {
             }
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn parse(html: &str) -> RcDom {
+            html5ever::parse_document(RcDom::default(), Default::default())
+                .from_utf8()
+                .read_from(&mut html.as_bytes())
+                .unwrap()
+        }
+
+        fn text(html: &str) -> String {
+            inner_text(&parse(html), &Config::new(Regex::new(".").unwrap()))
+        }
+
+        #[test]
+        fn inner_text_excludes_head() {
+            assert_eq!(
+                text("<html><head><title>foo</title></head><body>bar</body></html>"),
+                "bar"
+            );
+        }
+
+        #[test]
+        fn inner_text_excludes_scripts() {
+            assert_eq!(text("foo<script>let x = 1;</script>bar"), "foobar");
+        }
+
+        #[test]
+        fn inner_text_joins_nested_elements() {
+            assert_eq!(
+                text("<div>foo<p>bar<b>baz</b></p>qux</div>"),
+                "foobarbazqux"
+            );
+        }
+
+        #[test]
+        fn inner_text_includes_template_contents() {
+            assert_eq!(text("foo<template><p>bar</p></template>"), "foobar");
+        }
+
+        #[test]
+        fn inner_text_skips_self_closing_tags() {
+            assert_eq!(text("foo<br/>bar<img src=\"a.png\"/>baz"), "foobarbaz");
+        }
+
+        #[test]
+        fn inner_text_includes_html_attrs() {
+            let config = Config {
+                include_html_attrs: vec!["alt".to_owned()],
+                ..Config::new(Regex::new(".").unwrap())
+            };
+            assert_eq!(
+                inner_text(&parse(r#"foo<img alt="bar" src="a.png">baz"#), &config),
+                "foo bar baz"
+            );
+        }
+
+        #[test]
+        fn inner_text_skips_boilerplate_with_readability() {
+            let config = Config {
+                readability: true,
+                ..Config::new(Regex::new(".").unwrap())
+            };
+            assert_eq!(
+                inner_text(
+                    &parse(
+                        r#"<nav><ul><li><a href="/">Home</a></li><li><a href="/about">About</a></li></ul></nav>
+<main><p>Some <a href="/foo">foo</a> and plenty of bar.</p></main>
+<footer>See <a href="/terms">terms</a></footer>"#
+                    ),
+                    &config
+                ),
+                "\nSome foo and plenty of bar.\n"
+            );
+        }
+
+        #[test]
+        fn inner_text_skips_nested_boilerplate_with_readability() {
+            let config = Config {
+                readability: true,
+                ..Config::new(Regex::new(".").unwrap())
+            };
+            assert_eq!(
+                inner_text(
+                    &parse(
+                        r#"<div><p>Plenty of bar.</p><div><ul><li><a href="/a">Alpha</a></li><li><a href="/b">Beta</a></li></ul></div></div>"#
+                    ),
+                    &config
+                ),
+                "Plenty of bar."
+            );
+        }
+
+        #[test]
+        fn inner_text_keeps_unicode() {
+            assert_eq!(text("<p>héllo</p><p>世界 🌍</p>"), "héllo世界 🌍");
+        }
+
+        fn links_(html: &str) -> HashSet<String> {
+            links(
+                &Url::parse("http://foo.com/a/b").unwrap(),
+                &parse(html),
+                &Config::new(Regex::new(".").unwrap()),
+            )
+            .into_iter()
+            .map(String::from)
+            .collect()
+        }
+
+        fn strings(xs: &[&str]) -> HashSet<String> {
+            xs.iter().map(|&x| x.to_owned()).collect()
+        }
+
+        #[test]
+        fn links_resolves_relative_links() {
+            assert_eq!(
+                links_(r#"<a href="c">1</a><a href="../d">2</a>"#),
+                strings(&["http://foo.com/a/c", "http://foo.com/d"])
+            );
+        }
+
+        #[test]
+        fn links_resolves_root_relative_links() {
+            assert_eq!(
+                links_(r#"<a href="/c">1</a>"#),
+                strings(&["http://foo.com/c"])
+            );
+        }
+
+        #[test]
+        fn links_strips_fragments() {
+            assert_eq!(
+                links_(r#"<a href="/c#d">1</a>"#),
+                strings(&["http://foo.com/c"])
+            );
+        }
+
+        #[test]
+        fn links_keeps_queries() {
+            assert_eq!(
+                links_(r#"<a href="/c?d=1">1</a>"#),
+                strings(&["http://foo.com/c?d=1"])
+            );
+        }
+
+        #[test]
+        fn links_deduplicates() {
+            assert_eq!(
+                links_(r#"<a href="/c">1</a><a href="http://foo.com/c">2</a><a href="/c#d">3</a>"#),
+                strings(&["http://foo.com/c"])
+            );
+        }
+
+        #[test]
+        fn links_follows_hreflang_alternates() {
+            let html = r#"<link rel="alternate" hreflang="es-MX" href="/es-mx/"><link rel="alternate" hreflang="es" href="/es/"><link rel="alternate" hreflang="fr" href="/fr/"><link rel="alternate" type="application/rss+xml" href="/feed">"#;
+            assert_eq!(links_(html), strings(&[]));
+            assert_eq!(
+                links(
+                    &Url::parse("http://foo.com/").unwrap(),
+                    &parse(html),
+                    &Config {
+                        follow_hreflang: vec!["ES".to_owned()],
+                        ..Config::new(Regex::new(".").unwrap())
+                    },
+                )
+                .into_iter()
+                .map(String::from)
+                .collect::<HashSet<_>>(),
+                strings(&["http://foo.com/es-mx/", "http://foo.com/es/"])
+            );
+        }
+
+        #[test]
+        fn links_includes_areas() {
+            assert_eq!(
+                links_(r#"<map><area href="/c"></map>"#),
+                strings(&["http://foo.com/c"])
+            );
+        }
+
+        #[test]
+        fn links_skips_malformed_hrefs() {
+            assert_eq!(
+                links_(r#"<a href="http://[">1</a><a href="/c">2</a>"#),
+                strings(&["http://foo.com/c"])
+            );
+        }
+
+        #[test]
+        fn base_url_uses_first_base_with_href() {
+            let url = Url::parse("http://foo.com/a/b").unwrap();
+            assert_eq!(
+                base_url(
+                    &url,
+                    &parse(
+                        r#"<base target="_blank"><base href="/c/"><base href="/d/"><base target="_self">"#
+                    )
+                ),
+                Some(Url::parse("http://foo.com/c/").unwrap())
+            );
+        }
+
+        #[test]
+        fn canonical_url_uses_first_canonical_link() {
+            let url = Url::parse("http://foo.com/a/b").unwrap();
+            assert_eq!(
+                canonical_url(
+                    &url,
+                    &parse(
+                        r#"<link rel="canonical" href="/c"><link rel="canonical" href="/d"><link rel="canonical">"#
+                    )
+                ),
+                Some(Url::parse("http://foo.com/c").unwrap())
+            );
+        }
+
+        fn pdf() -> Vec<u8> {
+            save(document())
+        }
+
+        fn save(mut doc: lopdf::Document) -> Vec<u8> {
+            let mut buf = Vec::new();
+            doc.save_to(&mut buf).unwrap();
+            buf
+        }
+
+        fn document() -> lopdf::Document {
+            use lopdf::content::{Content, Operation};
+            use lopdf::{dictionary, Document, Object, Stream};
+
+            let mut doc = Document::with_version("1.5");
+            let pages_id = doc.new_object_id();
+            let font_id = doc.add_object(dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type1",
+                "BaseFont" => "Courier",
+            });
+            let content = Content {
+                operations: vec![
+                    Operation::new("BT", vec![]),
+                    Operation::new("Tf", vec!["F1".into(), 12.into()]),
+                    Operation::new("Td", vec![100.into(), 600.into()]),
+                    Operation::new("Tj", vec![Object::string_literal("Revenue grew")]),
+                    Operation::new("ET", vec![]),
+                ],
+            };
+            let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+            let page_id = doc.add_object(dictionary! {
+                "Type" => "Page",
+                "Parent" => pages_id,
+                "Contents" => content_id,
+                "Resources" => dictionary! { "Font" => dictionary! { "F1" => font_id } },
+            });
+            doc.objects.insert(
+                pages_id,
+                Object::Dictionary(dictionary! {
+                    "Type" => "Pages",
+                    "Kids" => vec![page_id.into()],
+                    "Count" => 1,
+                    "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+                }),
+            );
+            let catalog_id = doc.add_object(dictionary! {
+                "Type" => "Catalog",
+                "Pages" => pages_id,
+            });
+            let info_id = doc.add_object(dictionary! {
+                "Title" => Object::string_literal("Annual report"),
+                "Keywords" => Object::string_literal("finance"),
+            });
+            doc.trailer.set("Root", catalog_id);
+            doc.trailer.set("Info", info_id);
+            doc
+        }
+
+        #[test]
+        fn pdf_text_includes_metadata_and_text() {
+            let text = pdf_text(&pdf(), &Config::new(Regex::new(".").unwrap())).unwrap();
+            assert!(text.starts_with("Annual report\nfinance\n"));
+            assert!(text.contains("Revenue grew"));
+        }
+
+        #[test]
+        fn pdf_text_can_exclude_text() {
+            assert_eq!(
+                pdf_text(
+                    &pdf(),
+                    &Config {
+                        pdf_metadata_only: true,
+                        ..Config::new(Regex::new(".").unwrap())
+                    }
+                ),
+                Ok("Annual report\nfinance".to_owned())
+            );
+        }
+
+        #[test]
+        fn pdf_text_skips_unopened_encrypted_pdfs() {
+            use lopdf::{dictionary, Object};
+
+            let mut doc = document();
+            // A user password hash
+            // no password matches
+            let encrypt_id = doc.add_object(dictionary! {
+                "Filter" => "Standard",
+                "V" => 1,
+                "R" => 2,
+                "O" => Object::string_literal(vec![1; 32]),
+                "U" => Object::string_literal(vec![2; 32]),
+                "P" => -4,
+            });
+            doc.trailer.set("Encrypt", encrypt_id);
+            doc.trailer.set(
+                "ID",
+                vec![
+                    Object::string_literal(vec![3; 16]),
+                    Object::string_literal(vec![3; 16]),
+                ],
+            );
+            assert!(pdf_text(&save(doc), &Config::new(Regex::new(".").unwrap())).is_err());
+        }
+    }
 }
@@ -13,19 +13,33 @@ impl<K, V> MemCache<K, V> {
             inner: RwLock::new(HashMap::new()),
         }
     }
+}
 
-    pub fn clear(&self) {
-        self.inner.write().unwrap().clear();
+impl<K: Clone, V: Clone> Clone for MemCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: RwLock::new(self.inner.read().unwrap().clone()),
+        }
     }
 }
 
 impl<K: Clone + Eq + Hash, V: Clone> Cache<K, V> for MemCache<K, V> {
     fn get(&self, k: &K) -> Option<V> {
-        self.inner.read().unwrap().get(k).map(|x| x.clone())
+        self.inner.read().unwrap().get(k).cloned()
     }
 
-    fn set(&self, k: &K, v: &V) -> Result<(), Box<dyn std::error::Error>> {
+    fn set(&self, k: &K, v: &V) -> Result<(), webgrep::cache::Error> {
         self.inner.write().unwrap().insert(k.clone(), v.clone());
         Ok(())
     }
+
+    fn entries(&self) -> Result<Vec<(K, V)>, webgrep::cache::Error> {
+        Ok(self
+            .inner
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect())
+    }
 }
@@ -6,7 +6,7 @@ use reqwest::Url;
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 use std::num::NonZeroU16;
-use webgrep::client::{Body, Client, Response};
+use webgrep::client::{self, Body, Client, Fetched, Response};
 
 #[derive(Debug)]
 pub struct PseudorandomClient {
@@ -46,7 +46,23 @@ impl Client for PseudorandomClient {
                 )
             })
             .join("");
-        Ok(Body::Html(links))
+        Ok(Fetched {
+            status: 200,
+            body: Body::Html(links),
+        })
+    }
+
+    async fn head(&self, _url: &Url) -> Result<u16, client::Error> {
+        Ok(200)
+    }
+
+    async fn post_json(
+        &self,
+        _url: &Url,
+        _authorization: Option<&str>,
+        _body: String,
+    ) -> Result<(), client::Error> {
+        Ok(())
     }
 }
 
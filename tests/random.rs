@@ -11,9 +11,10 @@ use regex::Regex;
 use reqwest::Url;
 use std::iter::repeat_with;
 use std::num::{NonZeroU16, NonZeroUsize};
+use std::sync::Arc;
 use std::time::Duration;
 use webgrep::client::Response;
-use webgrep::run;
+use webgrep::{run, Config};
 
 const MAX_MAX_DEPTH: u64 = 2;
 
@@ -93,77 +94,71 @@ impl Arbitrary for RunParams {
 
 #[quickcheck_async::tokio]
 async fn run_is_idempotent_with_empty_cache(run_params: RunParams) {
-    let cache1 = mk_static(MemCache::new());
-    let cache2 = mk_static(MemCache::new());
+    let cache1 = Arc::new(MemCache::new());
+    let cache2 = Arc::new(MemCache::new());
     assert_eq!(
-        line_occurences(&run_(&run_params, cache1).await),
-        line_occurences(&run_(&run_params, cache2).await)
+        line_occurences(&run_(&run_params, &cache1).await),
+        line_occurences(&run_(&run_params, &cache2).await)
     );
-    cache1.clear();
-    cache2.clear();
 }
 
 #[quickcheck_async::tokio]
 async fn run_is_idempotent_with_full_cache(run_params: RunParams) {
-    let cache = mk_static(MemCache::new());
-    run_(&run_params, cache).await;
+    let cache = Arc::new(MemCache::new());
+    run_(&run_params, &cache).await;
     assert_eq!(
-        line_occurences(&run_(&run_params, cache).await),
-        line_occurences(&run_(&run_params, cache).await)
+        line_occurences(&run_(&run_params, &cache).await),
+        line_occurences(&run_(&run_params, &cache).await)
     );
-    cache.clear();
 }
 
 #[quickcheck_async::tokio]
 async fn run_is_idempotent_with_partial_cache(run_params: RunParamsWithReducedDepth) {
-    let cache1 = mk_static(MemCache::new());
-    run_(&run_params.get_reduced(), cache1).await;
-    let cache2 = cache1.clone();
+    let cache1 = Arc::new(MemCache::new());
+    run_(&run_params.get_reduced(), &cache1).await;
+    let cache2 = Arc::new((*cache1).clone());
     assert_eq!(
-        line_occurences(&run_(run_params.get(), cache1).await),
-        line_occurences(&run_(run_params.get(), cache2).await)
+        line_occurences(&run_(run_params.get(), &cache1).await),
+        line_occurences(&run_(run_params.get(), &cache2).await)
     );
-    cache1.clear();
-    cache2.clear();
 }
 
 #[quickcheck_async::tokio]
 async fn run_finds_the_same_matches_with_empty_or_full_cache(run_params: RunParams) {
-    let cache = mk_static(MemCache::new());
+    let cache = Arc::new(MemCache::new());
     assert_eq!(
-        line_occurences(&run_(&run_params, cache).await),
-        line_occurences(&run_(&run_params, cache).await)
+        line_occurences(&run_(&run_params, &cache).await),
+        line_occurences(&run_(&run_params, &cache).await)
     );
-    cache.clear();
 }
 
 #[quickcheck_async::tokio]
 async fn run_finds_the_same_matches_with_empty_or_partial_cache(
     run_params: RunParamsWithReducedDepth,
 ) {
-    let cache1 = mk_static(MemCache::new());
-    let cache2 = mk_static(MemCache::new());
-    run_(&run_params.get_reduced(), cache2).await;
+    let cache1 = Arc::new(MemCache::new());
+    let cache2 = Arc::new(MemCache::new());
+    run_(&run_params.get_reduced(), &cache2).await;
     assert_eq!(
-        line_occurences(&run_(run_params.get(), cache1).await),
-        line_occurences(&run_(run_params.get(), cache2).await)
+        line_occurences(&run_(run_params.get(), &cache1).await),
+        line_occurences(&run_(run_params.get(), &cache2).await)
     );
-    cache1.clear();
-    cache2.clear();
 }
 
-async fn run_(params: &RunParams, cache: &'static MemCache<Url, Response>) -> Vec<u8> {
+async fn run_(params: &RunParams, cache: &Arc<MemCache<Url, Response>>) -> Vec<u8> {
     let mut buffer = Vec::new();
     run(
         &mut buffer,
         indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
-        cache,
+        cache.clone(),
         params.client,
-        Duration::ZERO,
-        params.page_threads,
-        params.exclude_urls_re,
-        params.max_depth,
-        params.search_re,
+        mk_static(Config {
+            request_delay: Duration::ZERO,
+            page_threads: params.page_threads,
+            exclude_urls_re: params.exclude_urls_re.clone(),
+            max_depth: Some(params.max_depth),
+            ..Config::new(params.search_re.clone())
+        }),
         params.urls.clone(),
     )
     .await
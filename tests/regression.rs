@@ -3,51 +3,1279 @@ mod common;
 
 use crate::cache::MemCache;
 use crate::common::{line_occurences, mk_static};
+use base64::Engine;
+use futures::StreamExt;
+use itertools::Itertools;
 use lazy_static::__Deref;
 use regex::Regex;
 use reqwest::Url;
 use std::collections::HashMap;
 use std::num::NonZeroUsize;
 use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use webgrep::client::{self, Body, Client, Response};
-use webgrep::run;
+use webgrep::cache::Cache;
+use webgrep::client::{self, Body, Client, Fetched, Response};
+use webgrep::{run, Config, Crawler, NodePriority, Summary, Webhook};
 
 #[tokio::test(flavor = "multi_thread")]
 async fn run_finds_all_matches_with_empty_cache() {
-    let cache = mk_static(MemCache::new());
-    assert_eq!(&line_occurences(&run_(cache, 2).await), EXPECTED.deref());
-    cache.clear();
+    let cache = Arc::new(MemCache::new());
+    assert_eq!(&line_occurences(&run_(&cache, 2).await), EXPECTED.deref());
 }
 
 #[tokio::test(flavor = "multi_thread")]
 async fn run_finds_all_matches_with_full_cache() {
-    let cache = mk_static(MemCache::new());
-    run_(cache, 2).await;
-    assert_eq!(&line_occurences(&run_(cache, 2).await), EXPECTED.deref());
-    cache.clear();
+    let cache = Arc::new(MemCache::new());
+    run_(&cache, 2).await;
+    assert_eq!(&line_occurences(&run_(&cache, 2).await), EXPECTED.deref());
 }
 
 #[tokio::test(flavor = "multi_thread")]
 async fn run_finds_all_matches_with_partial_cache() {
-    let cache = mk_static(MemCache::new());
-    run_(cache, 1).await;
-    assert_eq!(&line_occurences(&run_(cache, 2).await), EXPECTED.deref());
-    cache.clear();
+    let cache = Arc::new(MemCache::new());
+    run_(&cache, 1).await;
+    assert_eq!(&line_occurences(&run_(&cache, 2).await), EXPECTED.deref());
 }
 
-async fn run_(cache: &'static MemCache<Url, Response>, max_depth: usize) -> Vec<u8> {
+#[tokio::test(flavor = "multi_thread")]
+async fn run_normalizes_links() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(
+                r#"<a href="http://bar.com/?b=2&a=1#top">1</a><a href="HTTP://BAR.com/?a=1&b=2">2</a>"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://bar.com/?a=1&b=2").unwrap(),
+            Body::Html(r#"bar"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(&run_with(client, test_config()).await),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/?a=1&b=2", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_strips_queries() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(
+                r#"<a href="http://bar.com/?ref=1">1</a><a href="http://bar.com/?ref=2">2</a>"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://bar.com/").unwrap(),
+            Body::Html(r#"bar"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    strip_query: true,
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/", 1)
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_skips_visited_urls() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(2),
+                    skip_visited: true,
+                    exact_visited: true,
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/", 1),
+            ("http://foo.com/ > http://foobar.com/", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_follows_css_links() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(
+                r#"<link rel="stylesheet" href="/a.css"><style>@import "http://bar.com/b.css";</style>"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://foo.com/a.css").unwrap(),
+            Body::Css(r#"body { background: url('bg.png') }"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://bar.com/b.css").unwrap(),
+            Body::Css(r#""#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    follow_css_links: true,
+                    search_re: Regex::new("").unwrap().into(),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://foo.com/a.css", 1),
+            ("http://foo.com/ > http://bar.com/b.css", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_resolves_links_against_base_url() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(r#"<base href="http://bar.com/baz/"><a href="qux">1</a>"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://bar.com/baz/qux").unwrap(),
+            Body::Html(r#"qux"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(&run_with(client, test_config()).await),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/baz/qux", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_strips_params() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(
+                r#"<a href="http://bar.com/?id=1&utm_source=a">1</a><a href="http://bar.com/?utm_source=b&id=1">2</a>"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://bar.com/?id=1").unwrap(),
+            Body::Html(r#"bar"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    strip_params: vec!["utm_source".to_owned()],
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/?id=1", 1)
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_skips_links_beyond_max_url_depth() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(r#"<a href="/a/b/">1</a><a href="/a/b/c">2</a>"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://foo.com/a/b/").unwrap(),
+            Body::Html(r#"b"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://foo.com/a/b/c").unwrap(),
+            Body::Html(r#"c"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    max_url_depth: Some(2),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://foo.com/a/b/", 1)
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_skips_blocked_domains() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(
+                r#"<a href="http://www.google-analytics.com/">1</a><a href="http://a.bar.com/">2</a><a href="http://foobar.com/">3</a>"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://www.google-analytics.com/").unwrap(),
+            Body::Html(r#"tracker"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://a.bar.com/").unwrap(),
+            Body::Html(r#"bar"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://foobar.com/").unwrap(),
+            Body::Html(r#"foobar"#.to_owned()),
+        ),
+    ]));
+    let config = test_config();
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    blocked_domains: config
+                        .blocked_domains
+                        .into_iter()
+                        .chain(["bar.com".to_owned()])
+                        .collect(),
+                    ..config
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://foobar.com/", 1)
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_only_searches_included_tags() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(
+                r#"<nav>needle<a href="http://bar.com/">1</a></nav><main>hay</main>"#.to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://bar.com/").unwrap(),
+            Body::Html(r#"<main><p>needle</p></main>"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    include_tags: vec!["main".to_owned()],
+                    ..Config::new(Regex::new("needle").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/ > http://bar.com/", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_requires_all_patterns_to_match() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(2),
+                    extra_search_res: vec![Regex::new("bar").unwrap().into()],
+                    ..Config::new(Regex::new("foo").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/ > http://foobar.com/", 1),
+            ("http://foo.com/ > http://bar.com/ > http://foobar.com/", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_matches_any_pattern() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(1),
+                    extra_search_res: vec![Regex::new("^bar").unwrap().into()],
+                    any_pattern: true,
+                    ..Config::new(Regex::new(r"^foo\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_inverts_matches() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(2),
+                    invert_match: true,
+                    ..Config::new(Regex::new("bar").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_replaces_matches() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    replace: Some("${1}baz".to_owned()),
+                    ..Config::new(Regex::new(r"(foo|bar)\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/: foobaz", 1),
+            ("http://foo.com/ > http://bar.com/: barbaz", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_extracts_named_groups() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    extract: Some("n".to_owned()),
+                    ..Config::new(Regex::new(r"(?:foo|bar)(?P<n>\d)").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("1", 2)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_prints_unique_extracts_once() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    extract: Some("n".to_owned()),
+                    unique_extracts: true,
+                    ..Config::new(Regex::new(r"(?:foo|bar)(?P<n>\d)").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("1", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_skips_pages_without_extracted_groups() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    extract: Some("n".to_owned()),
+                    ..Config::new(Regex::new(r"foo(?P<n>\d)|bar\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("1", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_only_searches_seeds_at_max_depth_0() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(0),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_follows_links_without_max_depth() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: None,
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        EXPECTED.clone()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_stops_following_links_after_shutdown() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(2),
+                    shutdown: AtomicBool::new(true),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_prints_only_matching() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    only_matching: true,
+                    ..Config::new(Regex::new(r"(foo|bar)\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/: foo1", 1),
+            ("http://foo.com/ > http://bar.com/: bar1", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_prints_only_matching_for_any_pattern() {
+    // `bar.com` matches only the extra pattern,
+    // so it has nothing to print.
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(1),
+                    extra_search_res: vec![Regex::new("^bar").unwrap().into()],
+                    any_pattern: true,
+                    only_matching: true,
+                    ..Config::new(Regex::new(r"^foo\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/: foo1", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_prints_at_most_max_matches_per_page() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    only_matching: true,
+                    max_matches_per_page: NonZeroUsize::new(1),
+                    ..Config::new(Regex::new(r"\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/: 1", 1),
+            ("http://foo.com/ > http://bar.com/: 1", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_formats_matches() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    format: Some("{depth} {url} {match} {unknown}".to_owned()),
+                    ..Config::new(Regex::new(r"(foo|bar)\d").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("0 http://foo.com/ foo1 {unknown}", 1),
+            ("1 http://bar.com/ bar1 {unknown}", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_searches_xml_text() {
+    let client = MapClient::new(HashMap::from([(
+        Url::from_str("http://foo.com/").unwrap(),
+        Body::Xml(r#"<?xml version="1.0"?><urlset><loc>it&apos;s</loc></urlset>"#.to_owned()),
+    )]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client.clone(),
+                Config {
+                    search_re: Regex::new("^it's$").unwrap().into(),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    search_re: Regex::new("loc").unwrap().into(),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::new()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_searches_json_values() {
+    let client = MapClient::new(HashMap::from([(
+        Url::from_str("http://foo.com/").unwrap(),
+        Body::Json(r#"{"needle": ["hay", {"key": "needle"}]}"#.to_owned()),
+    )]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client.clone(),
+                Config {
+                    search_json_values: true,
+                    ..Config::new(Regex::new("(?m)^needle$").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    search_json_values: true,
+                    ..Config::new(Regex::new("key").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::new()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_strips_markdown() {
+    let client = MapClient::new(HashMap::from([(
+        Url::from_str("http://foo.com/").unwrap(),
+        Body::Markdown(
+            "## A **bold** [needle](http://bar.com/)\n```rust\nhay\n```\n- `hay`".to_owned(),
+        ),
+    )]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client.clone(),
+                Config {
+                    extra_search_res: vec![Regex::new("(?m)^hay$").unwrap().into()],
+                    ..Config::new(Regex::new("(?m)^A bold needle$").unwrap())
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config::new(Regex::new(r"[#*`\[\]()-]|rust").unwrap())
+            )
+            .await
+        ),
+        HashMap::new()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_decodes_html_entities() {
+    let client = MapClient::new(HashMap::from([(
+        Url::from_str("http://foo.com/").unwrap(),
+        Body::Html(r#"<p>it&#39;s fish &amp; chips &amp;lt;</p>"#.to_owned()),
+    )]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config::new(Regex::new("it's fish & chips &lt;").unwrap())
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_starts_seeds_at_depth() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    max_depth: Some(2),
+                    seed_depths: HashMap::from([(Url::from_str("http://foo.com").unwrap(), 1)]),
+                    format: Some("{depth} {path}".to_owned()),
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("1 http://foo.com/", 1),
+            ("2 http://foo.com/ > http://bar.com/", 1),
+            ("2 http://foo.com/ > http://foobar.com/", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_encodes_international_domain_names() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(r#"<a href="http://münchen.de/">1</a>"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://xn--mnchen-3ya.de/").unwrap(),
+            Body::Html(r#"bar"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(&run_with(client, test_config()).await),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://xn--mnchen-3ya.de/", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_records_dead_links() {
+    let client = mk_static(MapClient::new(HashMap::from([(
+        Url::from_str("http://foo.com/").unwrap(),
+        Body::Html(r#"<a href="http://bar.com/">1</a>"#.to_owned()),
+    )])));
+    let cache = Arc::new(MemCache::new());
+    let summary = run(
+        std::io::sink(),
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache,
+        client,
+        mk_static(Config {
+            record_dead_links: true,
+            ..test_config()
+        }),
+        vec![Url::from_str("http://foo.com").unwrap()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        summary
+            .dead_links
+            .iter()
+            .map(|x| (x.path.iter().map(Url::as_str).collect(), x.status))
+            .collect::<Vec<(Vec<_>, _)>>(),
+        vec![(vec!["http://foo.com/", "http://bar.com/"], 404)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_searches_pages_under_canonical_url() {
+    let client = mk_static(MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(r#"<a href="http://bar.com/?page=1">1</a>"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://bar.com/?page=1").unwrap(),
+            Body::Html(
+                r#"<link rel="canonical" href="http://bar.com/"><a href="http://bar.com/?page=2">2</a>bar"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://bar.com/?page=2").unwrap(),
+            Body::Html(r#"<link rel="canonical" href="http://bar.com/">bar"#.to_owned()),
+        ),
+    ])));
+    for (skip_visited, expected) in [
+        (
+            true,
+            HashMap::from([
+                ("http://foo.com/", 1),
+                ("http://foo.com/ > http://bar.com/", 1),
+                (
+                    "http://foo.com/ > http://bar.com/ > http://bar.com/?page=2",
+                    1,
+                ),
+            ]),
+        ),
+        // Without visited URLs,
+        // every page naming a canonical URL
+        // is searched under it.
+        (
+            false,
+            HashMap::from([
+                ("http://foo.com/", 1),
+                ("http://foo.com/ > http://bar.com/", 1),
+                ("http://foo.com/ > http://bar.com/ > http://bar.com/", 1),
+            ]),
+        ),
+    ] {
+        let cache = Arc::new(MemCache::new());
+        let mut buffer = Vec::new();
+        run(
+            &mut buffer,
+            indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+            cache.clone(),
+            client,
+            mk_static(Config {
+                max_depth: Some(2),
+                skip_visited,
+                exact_visited: true,
+                ..test_config()
+            }),
+            vec![Url::from_str("http://foo.com").unwrap()],
+        )
+        .await
+        .unwrap();
+        assert_eq!(line_occurences(&buffer), expected);
+        // Pages naming a canonical URL
+        // don't stand in for it in the cache.
+        assert!(cache
+            .get(&Url::from_str("http://bar.com/").unwrap())
+            .is_none());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_ignores_canonical_urls_on_other_hosts() {
+    let client = mk_static(MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(r#"<a href="http://bar.com/">1</a>"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://bar.com/").unwrap(),
+            Body::Html(r#"<link rel="canonical" href="http://baz.com/">bar"#.to_owned()),
+        ),
+    ])));
+    let baz = Url::from_str("http://baz.com/").unwrap();
+    let cache = Arc::new(MemCache::new());
+    let baz_page = Ok(Fetched {
+        status: 200,
+        body: Body::Html("baz".to_owned()),
+    });
+    cache.set(&baz, &baz_page).unwrap();
+    let mut buffer = Vec::new();
+    run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache.clone(),
+        client,
+        mk_static(test_config()),
+        vec![Url::from_str("http://foo.com").unwrap()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        line_occurences(&buffer),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/", 1),
+        ])
+    );
+    assert_eq!(cache.get(&baz), Some(baz_page));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_searches_pages_with_visited_canonical_url() {
+    let client = MapClient::new(HashMap::from([
+        (
+            Url::from_str("http://foo.com/").unwrap(),
+            Body::Html(r#"<a href="http://foo.com/a">1</a>"#.to_owned()),
+        ),
+        (
+            Url::from_str("http://foo.com/a").unwrap(),
+            Body::Html(
+                r#"<link rel="canonical" href="http://foo.com/"><a href="http://foo.com/b">1</a>a"#
+                    .to_owned(),
+            ),
+        ),
+        (
+            Url::from_str("http://foo.com/b").unwrap(),
+            Body::Html(r#"b"#.to_owned()),
+        ),
+    ]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    max_depth: Some(2),
+                    skip_visited: true,
+                    exact_visited: true,
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://foo.com/a", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_starts_shallow_pages_first() {
+    assert_eq!(
+        run_cached(Config {
+            node_priority: NodePriority::ShallowFirst,
+            ..cached_config()
+        })
+        .await
+        .0,
+        [
+            "http://foo.com/",
+            "http://foo.com/ > http://bar.com/",
+            "http://foo.com/ > http://foobar.com/",
+            "http://foo.com/ > http://bar.com/ > http://foobar.com/",
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_starts_deep_pages_first() {
+    assert_eq!(
+        run_cached(Config {
+            node_priority: NodePriority::DeepFirst,
+            ..cached_config()
+        })
+        .await
+        .0,
+        [
+            "http://foo.com/",
+            "http://foo.com/ > http://bar.com/",
+            "http://foo.com/ > http://bar.com/ > http://foobar.com/",
+            "http://foo.com/ > http://foobar.com/",
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_drops_pages_beyond_queue_limit() {
+    let (lines, summary) = run_cached(Config {
+        node_priority: NodePriority::ShallowFirst,
+        queue_limit: Some(0),
+        ..cached_config()
+    })
+    .await;
+    assert_eq!(
+        lines,
+        [
+            "http://foo.com/",
+            "http://foo.com/ > http://bar.com/",
+            "http://foo.com/ > http://bar.com/ > http://foobar.com/",
+        ]
+    );
+    assert_eq!(summary.dropped, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_lists_links() {
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                TEST_CLIENT.clone(),
+                Config {
+                    list_links: true,
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([
+            ("http://foo.com/\thttp://bar.com/", 1),
+            ("http://foo.com/\thttp://foobar.com/", 1),
+        ])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_skips_unfollowable_links() {
+    let client = MapClient::new(HashMap::from([(
+        Url::from_str("http://foo.com/").unwrap(),
+        Body::Html(
+            r#"<a href="mailto:a@foo.com">1</a><a href="tel:123">2</a><a href="javascript:void(0)">3</a><a href="data:text/plain,foo">4</a><a href="/bar">5</a>"#
+                .to_owned(),
+        ),
+    )]));
+    assert_eq!(
+        line_occurences(
+            &run_with(
+                client,
+                Config {
+                    list_links: true,
+                    ..test_config()
+                }
+            )
+            .await
+        ),
+        HashMap::from([("http://foo.com/\thttp://foo.com/bar", 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_tree_indents_pages_by_depth() {
+    let (_, summary) = run_cached(Config {
+        record_crawl: true,
+        search_re: Regex::new("bar").unwrap().into(),
+        ..cached_config()
+    })
+    .await;
+    let mut tree = String::new();
+    webgrep::report::write_tree(&mut tree, &summary).unwrap();
+    assert_eq!(
+        tree,
+        "http://foo.com/
+  http://bar.com/ [matched]
+    http://foobar.com/ [matched]
+  http://foobar.com/ [matched]
+"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn write_sqlite_records_pages() {
+    let cache = Arc::new(MemCache::new());
+    let run_sqlite = || async {
+        let summary = run(
+            &mut Vec::new(),
+            indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+            cache.clone(),
+            TEST_CLIENT.deref(),
+            mk_static(Config {
+                max_depth: Some(2),
+                record_crawl: true,
+                seed_depths: HashMap::from([(Url::from_str("http://foo.com").unwrap(), 1)]),
+                ..test_config()
+            }),
+            vec![Url::from_str("http://foo.com").unwrap()],
+        )
+        .await
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("webgrep-write-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        webgrep::report::write_sqlite(&path, &summary).unwrap();
+        let db = rusqlite::Connection::open(&path).unwrap();
+        let rows: Vec<(String, i64, u16, bool)> = db
+            .prepare("SELECT url, depth, status, fetched_at IS NOT NULL FROM pages ORDER BY url")
+            .unwrap()
+            .query_map([], |x| Ok((x.get(0)?, x.get(1)?, x.get(2)?, x.get(3)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        rows
+    };
+    let rows = |fetched| {
+        vec![
+            ("http://bar.com/".to_owned(), 2, 200, fetched),
+            ("http://foo.com/".to_owned(), 1, 200, fetched),
+            ("http://foobar.com/".to_owned(), 2, 200, fetched),
+        ]
+    };
+    assert_eq!(run_sqlite().await, rows(true));
+    // We don't know when cached pages were fetched.
+    assert_eq!(run_sqlite().await, rows(false));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_posts_matches_to_webhook() {
+    let client = mk_static(TEST_CLIENT.clone());
+    let mut buffer = Vec::new();
+    run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        Arc::new(MemCache::new()),
+        client,
+        mk_static(Config {
+            webhook: Some(Webhook {
+                url: Url::from_str("http://hook.com/").unwrap(),
+                authorization: None,
+            }),
+            ..Config::new(Regex::new("foobar").unwrap())
+        }),
+        vec![Url::from_str("http://foo.com").unwrap()],
+    )
+    .await
+    .unwrap();
+    assert!(buffer.is_empty());
+    assert_eq!(
+        *client.posts.lock().unwrap(),
+        [(
+            Url::from_str("http://hook.com/").unwrap(),
+            r#"{"matched_url":"http://foobar.com/","path":["http://foo.com/","http://foobar.com/"],"snippet":"foobar"}"#.to_owned()
+        )]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_only_searches_cached_pages_in_dry_run() {
+    let cache = Arc::new(MemCache::new());
+    run_(&cache, 1).await;
+    cache
+        .set(
+            &Url::from_str("http://foo.com/").unwrap(),
+            &Ok(Fetched {
+                status: 200,
+                body: Body::Html(
+                    r#"foo<a href="http://bar.com/">1</a><a href="http://baz.com/">2</a>"#
+                        .to_owned(),
+                ),
+            }),
+        )
+        .unwrap();
+    let mut buffer = Vec::new();
+    let summary = run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache.clone(),
+        TEST_CLIENT.deref(),
+        mk_static(Config {
+            dry_run: true,
+            ..test_config()
+        }),
+        vec![Url::from_str("http://foo.com").unwrap()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        line_occurences(&buffer),
+        HashMap::from([
+            ("http://foo.com/", 1),
+            ("http://foo.com/ > http://bar.com/", 1),
+        ])
+    );
+    assert_eq!(
+        summary.would_fetch,
+        [Url::from_str("http://baz.com/").unwrap()]
+    );
+    assert!(cache
+        .get(&Url::from_str("http://baz.com/").unwrap())
+        .is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn crawl_yields_matches() {
+    let cache = Arc::new(MemCache::new());
+    let matches: Vec<_> = Crawler::new(
+        cache,
+        TEST_CLIENT.deref(),
+        mk_static(Config {
+            max_depth: Some(2),
+            ..test_config()
+        }),
+    )
+    .crawl(vec![Url::from_str("http://foo.com").unwrap()])
+    .map(Result::unwrap)
+    .collect()
+    .await;
+    let paths: Vec<_> = matches
+        .iter()
+        .map(|x| x.path.iter().map(Url::as_str).join(" > "))
+        .collect();
+    assert_eq!(
+        &line_occurences(paths.join("\n").as_bytes()),
+        EXPECTED.deref()
+    );
+    assert!(matches
+        .iter()
+        .all(|x| Some(&x.url) == x.path.last() && x.snippet.is_some()));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_writes_a_file_per_entry() {
+    let cache = Arc::new(MemCache::new());
+    run_(&cache, 2).await;
+    let mut archive = Vec::new();
+    webgrep::cache::export(&mut archive, cache.entries().unwrap()).unwrap();
+
+    let mut names: Vec<_> = tar::Archive::new(flate2::read::GzDecoder::new(archive.as_slice()))
+        .entries()
+        .unwrap()
+        .map(|x| x.unwrap().path().unwrap().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    let mut expected: Vec<_> = cache
+        .entries()
+        .unwrap()
+        .into_iter()
+        .map(|(k, _)| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(k.as_str()))
+        .collect();
+    expected.sort();
+    assert_eq!(names, expected);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn import_reads_exported_entries() {
+    let cache = Arc::new(MemCache::new());
+    run_(&cache, 2).await;
+    let mut archive = Vec::new();
+    webgrep::cache::export(&mut archive, cache.entries().unwrap()).unwrap();
+
+    let debug = |entries: Vec<(Url, Response)>| {
+        let mut xs: Vec<_> = entries.iter().map(|x| format!("{:?}", x)).collect();
+        xs.sort();
+        xs
+    };
+    assert_eq!(
+        debug(webgrep::cache::import(archive.as_slice()).unwrap()),
+        debug(cache.entries().unwrap())
+    );
+}
+
+async fn run_with(client: MapClient, config: Config) -> Vec<u8> {
+    let cache = Arc::new(MemCache::new());
     let mut buffer = Vec::new();
     run(
         &mut buffer,
         indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
         cache,
+        mk_static(client),
+        mk_static(config),
+        vec![Url::from_str("http://foo.com").unwrap()],
+    )
+    .await
+    .unwrap();
+    buffer
+}
+
+fn test_config() -> Config {
+    Config {
+        request_delay: Duration::ZERO,
+        ..Config::new(Regex::new(".").unwrap())
+    }
+}
+
+async fn run_(cache: &Arc<MemCache<Url, Response>>, max_depth: usize) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache.clone(),
         TEST_CLIENT.deref(),
-        Duration::ZERO,
-        NonZeroUsize::new(max_depth).unwrap(),
-        mk_static(None),
-        2,
-        mk_static(Regex::new(".").unwrap()),
+        mk_static(Config {
+            request_delay: Duration::ZERO,
+            page_threads: NonZeroUsize::new(max_depth).unwrap(),
+            max_depth: Some(2),
+            ..Config::new(Regex::new(".").unwrap())
+        }),
         vec![Url::from_str("http://foo.com").unwrap()],
     )
     .await
@@ -55,6 +1283,41 @@ async fn run_(cache: &'static MemCache<Url, Response>, max_depth: usize) -> Vec<
     buffer
 }
 
+// With every page cached
+// and one page searched at a time,
+// pages are searched in order of priority.
+async fn run_cached(config: Config) -> (Vec<String>, Summary) {
+    let cache = Arc::new(MemCache::new());
+    run_(&cache, 2).await;
+    let mut buffer = Vec::new();
+    let summary = run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache,
+        TEST_CLIENT.deref(),
+        mk_static(config),
+        vec![Url::from_str("http://foo.com").unwrap()],
+    )
+    .await
+    .unwrap();
+    (
+        String::from_utf8(buffer)
+            .unwrap()
+            .lines()
+            .map(str::to_owned)
+            .collect(),
+        summary,
+    )
+}
+
+fn cached_config() -> Config {
+    Config {
+        page_threads: NonZeroUsize::new(1).unwrap(),
+        max_depth: Some(2),
+        ..test_config()
+    }
+}
+
 lazy_static::lazy_static! {
     static ref EXPECTED: HashMap<&'static str, u32> = HashMap::from([
         ("http://foo.com/", 1),
@@ -83,13 +1346,19 @@ lazy_static::lazy_static! {
     ]));
 }
 
+#[derive(Clone)]
 pub struct MapClient {
     map: HashMap<Url, Body>,
+    /// Bodies posted to each URL
+    posts: Arc<Mutex<Vec<(Url, String)>>>,
 }
 
 impl MapClient {
-    pub const fn new(map: HashMap<Url, Body>) -> Self {
-        Self { map }
+    pub fn new(map: HashMap<Url, Body>) -> Self {
+        Self {
+            map,
+            posts: Arc::new(Mutex::new(Vec::new())),
+        }
     }
 }
 
@@ -98,7 +1367,22 @@ impl Client for MapClient {
     async fn get(&self, url: &Url) -> Response {
         self.map
             .get(url)
-            .map(|x| x.clone())
+            .cloned()
+            .map(|body| Fetched { status: 200, body })
             .ok_or(client::Error::Other(client::ReqwestError::Status(404)))
     }
+
+    async fn head(&self, url: &Url) -> Result<u16, client::Error> {
+        Ok(if self.map.contains_key(url) { 200 } else { 404 })
+    }
+
+    async fn post_json(
+        &self,
+        url: &Url,
+        _authorization: Option<&str>,
+        body: String,
+    ) -> Result<(), client::Error> {
+        self.posts.lock().unwrap().push((url.clone(), body));
+        Ok(())
+    }
 }
@@ -0,0 +1,70 @@
+use proptest::prelude::*;
+use reqwest::Url;
+use webgrep::client::{Body, Error, Fetched, ReqwestError, Response};
+
+fn body() -> impl Strategy<Value = Body> {
+    prop_oneof![
+        any::<String>().prop_map(Body::Html),
+        any::<Vec<u8>>().prop_map(Body::Pdf),
+        any::<String>().prop_map(Body::Plain),
+        any::<String>().prop_map(Body::Css),
+        any::<String>().prop_map(Body::Script),
+        any::<String>().prop_map(Body::Xml),
+        any::<String>().prop_map(Body::Json),
+        any::<String>().prop_map(Body::Markdown),
+    ]
+}
+
+fn reqwest_error() -> impl Strategy<Value = ReqwestError> {
+    prop_oneof![
+        Just(ReqwestError::Builder),
+        Just(ReqwestError::Redirect),
+        any::<u16>().prop_map(ReqwestError::Status),
+        Just(ReqwestError::Timeout),
+        Just(ReqwestError::Request),
+        Just(ReqwestError::Connect),
+        Just(ReqwestError::Body),
+        Just(ReqwestError::Decode),
+        any::<String>().prop_map(ReqwestError::Other),
+    ]
+}
+
+fn error() -> impl Strategy<Value = Error> {
+    prop_oneof![
+        any::<String>().prop_map(Error::InvalidContentType),
+        any::<String>().prop_map(Error::SkippedContentType),
+        any::<Option<u64>>().prop_map(Error::ContentLengthTooLong),
+        reqwest_error().prop_map(Error::Other),
+    ]
+}
+
+fn response() -> impl Strategy<Value = Response> {
+    prop_oneof![
+        (any::<u16>(), body()).prop_map(|(status, body)| Ok(Fetched { status, body })),
+        error().prop_map(Err)
+    ]
+}
+
+proptest! {
+    #[test]
+    fn cached_responses_round_trip(url in "[a-z]{1,10}", response in response()) {
+        // File and sled caches store each entry with its key.
+        let url = Url::parse(&format!("http://{}.com/", url)).unwrap();
+        let entry = (url, response);
+        prop_assert_eq!(
+            bincode::deserialize::<(Url, Response)>(&bincode::serialize(&entry).unwrap()).unwrap(),
+            entry
+        );
+    }
+
+    #[test]
+    fn exported_responses_round_trip(url in "[a-z]{1,10}", response in response()) {
+        let url = Url::parse(&format!("http://{}.com/", url)).unwrap();
+        let mut archive = Vec::new();
+        webgrep::cache::export(&mut archive, [(&url, &response)]).unwrap();
+        prop_assert_eq!(
+            webgrep::cache::import::<Url, Response>(archive.as_slice()).unwrap(),
+            vec![(url, response)]
+        );
+    }
+}
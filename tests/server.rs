@@ -0,0 +1,313 @@
+mod cache;
+mod common;
+
+use crate::cache::MemCache;
+use crate::common::{line_occurences, mk_static};
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use webgrep::cache::Cache;
+use webgrep::client::{Client, Error, HttpClient};
+use webgrep::{run, Config};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_finds_matches_on_server() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/",
+        "text/html",
+        r#"<a href="/a">a</a><a href="/b">b</a>"#,
+    )
+    .await;
+    mount(&server, "/a", "text/html", "<p>foo</p>").await;
+    mount(&server, "/b", "text/html", "<p>bar</p>").await;
+    let output = run_with(&server, "foo").await;
+    assert_eq!(
+        line_occurences(&output),
+        HashMap::from([(format!("{0}/ > {0}/a", server.uri()).as_str(), 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_finds_matches_across_elements_on_server() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/",
+        "text/html",
+        "<html><head><title>foo</title></head><body><p>f<b>o</b><i>o</i></p></body></html>",
+    )
+    .await;
+    let output = run_with(&server, "foo").await;
+    assert_eq!(
+        line_occurences(&output),
+        HashMap::from([(format!("{}/", server.uri()).as_str(), 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_finds_matches_in_plain_text_on_server() {
+    let server = MockServer::start().await;
+    mount(&server, "/", "text/html", r#"<a href="/a.txt">a</a>"#).await;
+    mount(&server, "/a.txt", "text/plain; charset=utf-8", "foo").await;
+    let output = run_with(&server, "foo").await;
+    assert_eq!(
+        line_occurences(&output),
+        HashMap::from([(format!("{0}/ > {0}/a.txt", server.uri()).as_str(), 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_skips_get_after_failed_head() {
+    let server = MockServer::start().await;
+    mount(
+        &server,
+        "/",
+        "text/html",
+        r#"<a href="/a">a</a><a href="/b">b</a><a href="/c">c</a>"#,
+    )
+    .await;
+    mount(&server, "/a", "text/html", "<p>foo</p>").await;
+    mount(&server, "/b", "text/html", "<p>foo</p>").await;
+    mount(&server, "/c", "text/html", "<p>foo</p>").await;
+    for (route, status) in [("/", 200), ("/a", 404), ("/b", 405), ("/c", 501)] {
+        Mock::given(method("HEAD"))
+            .and(path(route))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(&server)
+            .await;
+    }
+    let cache = Arc::new(MemCache::new());
+    let mut buffer = Vec::new();
+    let summary = run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache.clone(),
+        mk_static(reqwest::Client::new()),
+        mk_static(Config {
+            probe_methods: true,
+            ..config("foo")
+        }),
+        vec![Url::parse(&server.uri()).unwrap()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        line_occurences(&buffer),
+        HashMap::from([
+            (format!("{0}/ > {0}/b", server.uri()).as_str(), 1),
+            (format!("{0}/ > {0}/c", server.uri()).as_str(), 1),
+        ])
+    );
+    assert!(!server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .any(|r| r.method == wiremock::http::Method::Get && r.url.path() == "/a"));
+    // A failed `HEAD` isn't a failed `GET`.
+    assert!(cache
+        .get(&Url::parse(&format!("{}/a", server.uri())).unwrap())
+        .is_none());
+    let mut get_only: Vec<_> = summary.get_only.iter().map(Url::path).collect();
+    get_only.sort_unstable();
+    assert_eq!(get_only, ["/b", "/c"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_searches_and_records_error_pages_on_server() {
+    let server = MockServer::start().await;
+    mount(&server, "/", "text/html", r#"<a href="/a">a</a>"#).await;
+    Mock::given(method("GET"))
+        .and(path("/a"))
+        .respond_with(ResponseTemplate::new(404).set_body_raw("<p>foo not found</p>", "text/html"))
+        .mount(&server)
+        .await;
+    let mut buffer = Vec::new();
+    let summary = run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        Arc::new(MemCache::new()),
+        mk_static(reqwest::Client::new()),
+        mk_static(Config {
+            record_dead_links: true,
+            ..config("foo")
+        }),
+        vec![Url::parse(&server.uri()).unwrap()],
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        line_occurences(&buffer),
+        HashMap::from([(format!("{0}/ > {0}/a", server.uri()).as_str(), 1)])
+    );
+    assert_eq!(
+        summary
+            .dead_links
+            .iter()
+            .map(|x| (x.path.iter().map(Url::to_string).collect(), x.status))
+            .collect::<Vec<(Vec<_>, _)>>(),
+        vec![(
+            vec![format!("{}/", server.uri()), format!("{}/a", server.uri())],
+            404
+        )]
+    );
+    assert_eq!(summary.errors, 0);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_gets_pages_skipped_by_type_in_later_runs() {
+    let server = MockServer::start().await;
+    mount(&server, "/", "text/html", r#"<a href="/a">a</a>"#).await;
+    mount(&server, "/a", "text/plain", "foo").await;
+    let cache = Arc::new(MemCache::new());
+    assert_eq!(
+        run_with_client(
+            &server,
+            cache.clone(),
+            HttpClient::new(reqwest::Client::new()).with_skip_types(vec!["text/plain".to_owned()])
+        )
+        .await,
+        b""
+    );
+    assert_eq!(
+        line_occurences(
+            &run_with_client(&server, cache, HttpClient::new(reqwest::Client::new())).await
+        ),
+        HashMap::from([(format!("{0}/ > {0}/a", server.uri()).as_str(), 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn run_gets_pages_over_size_limit_in_later_runs() {
+    let server = MockServer::start().await;
+    mount(&server, "/", "text/html", r#"<a href="/a">a</a>"#).await;
+    mount(&server, "/a", "text/plain", &"foo".repeat(100)).await;
+    let cache = Arc::new(MemCache::new());
+    assert_eq!(
+        run_with_client(
+            &server,
+            cache.clone(),
+            HttpClient::new(reqwest::Client::new()).with_max_body_size(100)
+        )
+        .await,
+        b""
+    );
+    assert_eq!(
+        line_occurences(
+            &run_with_client(&server, cache, HttpClient::new(reqwest::Client::new())).await
+        ),
+        HashMap::from([(format!("{0}/ > {0}/a", server.uri()).as_str(), 1)])
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn http_client_fails_bodies_over_size_limit() {
+    let server = MockServer::start().await;
+    mount(&server, "/", "text/plain", &"a".repeat(100)).await;
+    let url = Url::parse(&server.uri()).unwrap();
+    let client = |n| HttpClient::new(reqwest::Client::new()).with_max_body_size(n);
+    assert_eq!(
+        client(99).get(&url).await,
+        Err(Error::ContentLengthTooLong(Some(100)))
+    );
+    assert!(client(100).get(&url).await.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn http_client_fails_streamed_bodies_over_size_limit() {
+    let client = HttpClient::new(reqwest::Client::new()).with_max_body_size(1000);
+    assert_eq!(
+        client.get(&serve_streamed(1001).await).await,
+        Err(Error::ContentLengthTooLong(None))
+    );
+    assert!(client.get(&serve_streamed(1000).await).await.is_ok());
+    assert_eq!(
+        client.get(&serve_streamed(100 * 1024).await).await,
+        Err(Error::ContentLengthTooLong(None))
+    );
+}
+
+/// Serve one response of `n` bytes
+/// without `content-length`,
+/// so the body ends when the connection closes.
+async fn serve_streamed(n: usize) -> Url {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let _ = socket.read(&mut [0; 1024]).await;
+        socket
+            .write_all(b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        // The client may hang up
+        // once the body is too long.
+        for chunk in vec![b'a'; n].chunks(1024) {
+            if socket.write_all(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    url
+}
+
+async fn mount(server: &MockServer, route: &str, content_type: &str, body: &str) {
+    Mock::given(method("GET"))
+        .and(path(route))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(body, content_type))
+        .mount(server)
+        .await;
+}
+
+fn config(pattern: &str) -> Config {
+    Config {
+        request_delay: Duration::ZERO,
+        max_depth: Some(1),
+        ..Config::new(Regex::new(pattern).unwrap())
+    }
+}
+
+async fn run_with(server: &MockServer, pattern: &str) -> Vec<u8> {
+    run_with_config(server, config(pattern)).await
+}
+
+async fn run_with_client(
+    server: &MockServer,
+    cache: Arc<MemCache<Url, webgrep::client::Response>>,
+    client: HttpClient,
+) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        cache,
+        mk_static(client),
+        mk_static(config("foo")),
+        vec![Url::parse(&server.uri()).unwrap()],
+    )
+    .await
+    .unwrap();
+    buffer
+}
+
+async fn run_with_config(server: &MockServer, config: Config) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    run(
+        &mut buffer,
+        indicatif::MultiProgress::with_draw_target(indicatif::ProgressDrawTarget::hidden()),
+        Arc::new(MemCache::new()),
+        mk_static(reqwest::Client::new()),
+        mk_static(config),
+        vec![Url::parse(&server.uri()).unwrap()],
+    )
+    .await
+    .unwrap();
+    buffer
+}